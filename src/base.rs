@@ -1,31 +1,5 @@
 
 
-#[derive(Debug, Clone)]
-pub struct Graphs {
-    pub volume: Vec<[f64;2]>,
-    pub vcd: Vec<[f64;2]>,
-    pub glucose: Vec<[f64;2]>,
-    pub glutamin: Vec<[f64;2]>,
-    pub c_O2: Vec<[f64;2]>,
-    pub O2: Vec<[f64;2]>,
-    pub product: Vec<[f64;2]>,
-}
-
-impl Graphs {
-    pub fn default() -> Self {
-        Self {
-            volume: Vec::new(),
-            vcd: Vec::new(),
-            glucose: Vec::new(),
-            glutamin: Vec::new(),
-            c_O2: Vec::new(),
-            O2: Vec::new(),
-            product: Vec::new(),
-        }
-    }
-
-}
-
 #[derive(Clone)]
 pub struct Initial {
     pub vcd: f64,
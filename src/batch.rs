@@ -0,0 +1,65 @@
+use crate::{model::{Bioreactor, Feeding, Initial}, parameter::Parameter, ui::tree::Tree};
+
+/// One run: its measurements and how it was started and fed.
+/// Everything else comes from the shared bioreactor.
+#[derive(Debug, Clone)]
+pub struct Batch {
+    pub name: String,
+    pub nodes: Tree,
+    pub initial: Initial,
+    pub feeding: Feeding,
+    /// values fitted for this batch alone
+    pub overrides: Vec<(&'static Parameter, f64)>,
+}
+
+impl Batch {
+    pub fn new(name: String, nodes: Tree, sim: &Bioreactor) -> Self {
+        Self {
+            name,
+            nodes,
+            initial: sim.initial.clone(),
+            feeding: sim.feeding.clone(),
+            overrides: Vec::new(),
+        }
+    }
+
+    /// the shared bioreactor as it ran in this batch
+    pub fn bioreactor(&self, shared: &Bioreactor) -> Bioreactor {
+        let mut sim = shared.clone();
+        sim.initial = self.initial.clone();
+        sim.feeding = self.feeding.clone();
+        for (target, val) in &self.overrides {
+            target.set(&mut sim, *val);
+        }
+        sim
+    }
+
+    pub fn set_override(&mut self, target: &'static Parameter, val: f64) {
+        match self.overrides.iter_mut().find(|(t, _)| *t == target) {
+            Some((_, old)) => *old = val,
+            None => self.overrides.push((target, val)),
+        }
+    }
+
+    pub fn clear_override(&mut self, target: &Parameter) {
+        self.overrides.retain(|(t, _)| *t != target);
+    }
+
+    /// sets a shared value that lives in the batch, like an initial condition
+    pub fn update(&mut self, shared: &Bioreactor, target: &Parameter, val: f64) {
+        let mut sim = self.bioreactor(shared);
+        target.set(&mut sim, val);
+        self.initial = sim.initial;
+        self.feeding = sim.feeding;
+    }
+
+    /// takes over the edits made while this batch was shown
+    pub fn store(&mut self, nodes: &Tree, sim: &Bioreactor) {
+        self.nodes = nodes.clone();
+        self.initial = sim.initial.clone();
+        self.feeding = sim.feeding.clone();
+        for (target, val) in self.overrides.iter_mut() {
+            *val = target.get(sim);
+        }
+    }
+}
@@ -0,0 +1,203 @@
+use argmin::core::{CostFunction, Error, IterState, Problem, Solver, State as SolverState, TerminationReason, TerminationStatus, KV};
+use nalgebra::{DMatrix, DVector};
+use rand::{rngs::StdRng, SeedableRng};
+use rand_distr::StandardNormal;
+
+/// Covariance matrix adaptation evolution strategy (Hansen's `purecma`) on an unbounded space.
+/// Every generation samples `population` points around the mean and is costed in one `bulk_cost` call.
+pub struct CmaEs {
+    population: usize,
+    sigma: f64,
+    /// stops once every search direction is shorter than this
+    tolerance: f64,
+    /// coordinates are clamped to ±bound, far out the cost is flat and the search would drift
+    bound: f64,
+    rng: StdRng,
+    // strategy state, set up in `init` once the dimension is known
+    mean: DVector<f64>,
+    covariance: DMatrix<f64>,
+    /// eigenvectors and square roots of the eigenvalues of `covariance`
+    basis: DMatrix<f64>,
+    scales: DVector<f64>,
+    path_c: DVector<f64>,
+    path_sigma: DVector<f64>,
+    weights: Vec<f64>,
+    generation: u64,
+}
+
+impl CmaEs {
+    pub fn new(population: usize, sigma: f64, tolerance: f64, bound: f64) -> Self {
+        Self {
+            population: population.max(4),
+            sigma,
+            tolerance,
+            bound,
+            rng: StdRng::from_entropy(),
+            mean: DVector::zeros(0),
+            covariance: DMatrix::zeros(0, 0),
+            basis: DMatrix::zeros(0, 0),
+            scales: DVector::zeros(0),
+            path_c: DVector::zeros(0),
+            path_sigma: DVector::zeros(0),
+            weights: Vec::new(),
+            generation: 0,
+        }
+    }
+
+    /// fixes the random samples, e.g. for reproducible tests
+    pub fn with_seed(mut self, seed: u64) -> Self {
+        self.rng = StdRng::seed_from_u64(seed);
+        self
+    }
+
+    fn mueff(&self) -> f64 {
+        self.weights.iter().sum::<f64>().powi(2) / self.weights.iter().map(|w| w * w).sum::<f64>()
+    }
+
+    /// refreshes `basis` and `scales` from the covariance
+    fn decompose(&mut self) {
+        let symmetric = (&self.covariance + self.covariance.transpose()) * 0.5;
+        let eigen = symmetric.symmetric_eigen();
+        self.scales = eigen.eigenvalues.map(|value| value.max(1e-20).sqrt());
+        self.basis = eigen.eigenvectors;
+    }
+}
+
+impl<O> Solver<O, IterState<Vec<f64>, (), (), (), (), f64>> for CmaEs
+where
+    O: CostFunction<Param = Vec<f64>, Output = f64> + Sync,
+{
+    const NAME: &'static str = "CMA-ES";
+
+    fn init(&mut self, problem: &mut Problem<O>, state: IterState<Vec<f64>, (), (), (), (), f64>) -> Result<(IterState<Vec<f64>, (), (), (), (), f64>, Option<KV>), Error> {
+        let start = state.get_param().cloned().ok_or_else(|| Error::msg("CMA-ES needs a starting point"))?;
+        let n = start.len();
+        if n == 0 {
+            return Err(Error::msg("CMA-ES needs at least one parameter"));
+        }
+        // the best half of each generation moves the mean, better ranks weigh more
+        let mu = self.population / 2;
+        let weights: Vec<f64> = (1..=mu).map(|i| (mu as f64 + 0.5).ln() - (i as f64).ln()).collect();
+        let total: f64 = weights.iter().sum();
+        self.weights = weights.iter().map(|w| w / total).collect();
+
+        self.mean = DVector::from_vec(start.clone());
+        self.covariance = DMatrix::identity(n, n);
+        self.basis = DMatrix::identity(n, n);
+        self.scales = DVector::from_element(n, 1.);
+        self.path_c = DVector::zeros(n);
+        self.path_sigma = DVector::zeros(n);
+        self.generation = 0;
+
+        let cost = problem.cost(&start)?;
+        Ok((state.param(start).cost(cost), None))
+    }
+
+    fn next_iter(&mut self, problem: &mut Problem<O>, state: IterState<Vec<f64>, (), (), (), (), f64>) -> Result<(IterState<Vec<f64>, (), (), (), (), f64>, Option<KV>), Error> {
+        let n = self.mean.len();
+        let nf = n as f64;
+        let mueff = self.mueff();
+        let cc = (4. + mueff / nf) / (nf + 4. + 2. * mueff / nf);
+        let cs = (mueff + 2.) / (nf + mueff + 5.);
+        let c1 = 2. / ((nf + 1.3).powi(2) + mueff);
+        let cmu = (1. - c1).min(2. * (mueff - 2. + 1. / mueff) / ((nf + 2.).powi(2) + mueff));
+        let damps = 1. + 2. * (((mueff - 1.) / (nf + 1.)).sqrt() - 1.).max(0.) + cs;
+        // expected length of a standard normal vector
+        let chi_n = nf.sqrt() * (1. - 1. / (4. * nf) + 1. / (21. * nf * nf));
+
+        let transform = &self.basis * DMatrix::from_diagonal(&self.scales);
+        let candidates: Vec<Vec<f64>> = (0..self.population).map(|_| {
+            let z = DVector::from_fn(n, |_, _| rand::Rng::sample::<f64, _>(&mut self.rng, StandardNormal));
+            let x = &self.mean + &transform * z * self.sigma;
+            x.iter().map(|v| v.clamp(-self.bound, self.bound)).collect()
+        }).collect();
+        let costs = problem.bulk_cost(&candidates)?;
+
+        let mut order: Vec<usize> = (0..candidates.len()).collect();
+        order.sort_by(|a, b| costs[*a].total_cmp(&costs[*b]));
+        let steps: Vec<DVector<f64>> = order.iter().take(self.weights.len())
+            .map(|i| (DVector::from_column_slice(&candidates[*i]) - &self.mean) / self.sigma)
+            .collect();
+        let step = steps.iter().zip(&self.weights).fold(DVector::zeros(n), |sum, (y, w)| sum + y * *w);
+        self.mean += &step * self.sigma;
+
+        // evolution paths, the one for sigma in the whitened space
+        let inv_sqrt = &self.basis * DMatrix::from_diagonal(&self.scales.map(|s| 1. / s)) * self.basis.transpose();
+        self.path_sigma = &self.path_sigma * (1. - cs) + &inv_sqrt * &step * (cs * (2. - cs) * mueff).sqrt();
+        self.generation += 1;
+        let norm = self.path_sigma.norm();
+        let stalled = norm / (1. - (1. - cs).powi(2 * self.generation as i32)).sqrt() / chi_n >= 1.4 + 2. / (nf + 1.);
+        let h_sigma = if stalled { 0. } else { 1. };
+        self.path_c = &self.path_c * (1. - cc) + &step * (h_sigma * (cc * (2. - cc) * mueff).sqrt());
+
+        let rank_mu = steps.iter().zip(&self.weights).fold(DMatrix::zeros(n, n), |sum, (y, w)| sum + y * y.transpose() * *w);
+        let decay = 1. - c1 - cmu + (1. - h_sigma) * c1 * cc * (2. - cc);
+        self.covariance = &self.covariance * decay + &self.path_c * self.path_c.transpose() * c1 + rank_mu * cmu;
+        self.sigma *= ((cs / damps) * (norm / chi_n - 1.)).exp();
+        self.decompose();
+
+        let best = order[0];
+        let mut kv = KV::new();
+        kv.insert("sigma", self.sigma.into());
+        Ok((state.param(candidates[best].clone()).cost(costs[best]), Some(kv)))
+    }
+
+    fn terminate(&mut self, _state: &IterState<Vec<f64>, (), (), (), (), f64>) -> TerminationStatus {
+        if !self.sigma.is_finite() {
+            return TerminationStatus::Terminated(TerminationReason::SolverExit("step size diverged".to_string()));
+        }
+        let longest = self.scales.iter().fold(0f64, |max, s| max.max(*s));
+        if self.generation > 0 && self.sigma * longest < self.tolerance {
+            return TerminationStatus::Terminated(TerminationReason::SolverConverged);
+        }
+        TerminationStatus::NotTerminated
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use argmin::core::Executor;
+
+    use super::*;
+
+    struct Rosenbrock;
+    impl CostFunction for Rosenbrock {
+        type Param = Vec<f64>;
+        type Output = f64;
+
+        fn cost(&self, x: &Self::Param) -> Result<f64, Error> {
+            Ok(x.windows(2).map(|w| 100. * (w[1] - w[0] * w[0]).powi(2) + (1. - w[0]).powi(2)).sum())
+        }
+    }
+
+    #[test]
+    fn finds_the_rosenbrock_minimum() {
+        let res = Executor::new(Rosenbrock, CmaEs::new(12, 0.5, 1e-10, 12.).with_seed(1))
+            .configure(|state| state.param(vec![-1.2, 1., 0.5]).max_iters(2000))
+            .run()
+            .unwrap();
+        let best = res.state.get_best_param().unwrap();
+        assert!(best.iter().all(|x| (x - 1.).abs() < 1e-4), "{:?}", best);
+        assert_eq!(res.state.get_termination_reason(), Some(&TerminationReason::SolverConverged));
+    }
+
+    #[test]
+    fn stays_inside_the_bound() {
+        // the minimum lies outside, so the search ends up on the bound
+        struct Slope;
+        impl CostFunction for Slope {
+            type Param = Vec<f64>;
+            type Output = f64;
+
+            fn cost(&self, x: &Self::Param) -> Result<f64, Error> {
+                Ok(x.iter().sum())
+            }
+        }
+        let res = Executor::new(Slope, CmaEs::new(8, 1., 1e-8, 3.).with_seed(2))
+            .configure(|state| state.param(vec![0., 0.]).max_iters(300))
+            .run()
+            .unwrap();
+        let best = res.state.get_best_param().unwrap();
+        assert!(best.iter().all(|x| (x + 3.).abs() < 1e-3), "{:?}", best);
+    }
+}
@@ -0,0 +1,35 @@
+use std::sync::{atomic::{AtomicBool, Ordering}, mpsc};
+use std::thread;
+
+use rayon::prelude::*;
+
+use crate::{model::Bioreactor, simulation::{simulate, SimSettings, SimulationResult}, solver::SolverError};
+
+/// Simulates every bioreactor of `sims` on the rayon thread pool, results keep the order of `sims`
+pub fn simulate_all(sims: &[Bioreactor], settings: &SimSettings) -> Vec<Result<SimulationResult, SolverError>> {
+    sims.par_iter().map(|sim| simulate(sim, settings)).collect()
+}
+
+/// Like `simulate_all`, but calls `progress` with the number of finished runs on the calling thread.
+/// Runs not yet started when `cancel` is set are `None`.
+pub fn simulate_each<F>(sims: &[Bioreactor], settings: &SimSettings, cancel: &AtomicBool, mut progress: F) -> Vec<Option<Result<SimulationResult, SolverError>>>
+where
+    F: FnMut(usize),
+{
+    let (sender, receiver) = mpsc::channel();
+    thread::scope(|scope| {
+        let handle = scope.spawn(move || sims.par_iter().map(|sim| {
+            if cancel.load(Ordering::Relaxed) {
+                return None;
+            }
+            let res = simulate(sim, settings);
+            let _ = sender.send(());
+            Some(res)
+        }).collect::<Vec<_>>());
+        // ends once the pool is done and the sender is dropped with the job
+        for (i, _) in receiver.iter().enumerate() {
+            progress(i + 1);
+        }
+        handle.join().unwrap_or_else(|_| sims.iter().map(|_| None).collect())
+    })
+}
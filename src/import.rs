@@ -0,0 +1,447 @@
+use std::path::Path;
+
+use calamine::{open_workbook_auto, Data, DataType, Reader};
+use chrono::{DateTime, NaiveDate, NaiveDateTime};
+use serde::{Deserialize, Serialize};
+
+use crate::regressor::Group;
+
+/// Cells of a measurement file as text, before any column is interpreted
+#[derive(Debug, Clone, Default)]
+pub struct Table {
+    pub headers: Vec<String>,
+    pub rows: Vec<Vec<String>>,
+}
+impl Table {
+    pub fn from_csv(content: &str, delimiter: u8) -> Result<Self, String> {
+        let mut rdr = csv::ReaderBuilder::new()
+            .delimiter(delimiter)
+            .flexible(true)
+            .trim(csv::Trim::All)
+            .from_reader(content.as_bytes());
+        let headers = rdr.headers().map_err(|er| er.to_string())?.iter().map(|h| h.to_string()).collect();
+        let rows = rdr.records()
+            .map(|record| record.map(|record| record.iter().map(|cell| cell.to_string()).collect()))
+            .collect::<Result<Vec<Vec<String>>, _>>()
+            .map_err(|er| er.to_string())?;
+        Ok(Self { headers, rows })
+    }
+
+    /// the delimiter that splits the header line into the most columns
+    pub fn sniff_delimiter(content: &str) -> u8 {
+        let header = content.lines().next().unwrap_or_default();
+        *[b',', b';', b'\t'].iter().max_by_key(|d| header.matches(**d as char).count()).unwrap()
+    }
+
+    /// the cells of `range` in `grid`, its first row naming the columns.
+    /// An empty range takes the whole sheet, `B3` alone reads from there to the end.
+    pub fn from_grid(grid: &[Vec<String>], range: &str) -> Result<Self, String> {
+        let range = range.trim();
+        let ((row, col), end) = if range.is_empty() {
+            ((0, 0), None)
+        } else {
+            match range.split_once(':') {
+                Some((first, last)) => (parse_cell(first)?, Some(parse_cell(last)?)),
+                None => (parse_cell(range)?, None),
+            }
+        };
+        let (last_row, last_col) = end.unwrap_or((usize::MAX, usize::MAX));
+        if last_row < row || last_col < col {
+            return Err(format!("range {} ends before it starts", range));
+        }
+        let mut rows = grid.iter().skip(row).take((last_row - row).saturating_add(1))
+            .map(|cells| cells.iter().skip(col).take((last_col - col).saturating_add(1)).cloned().collect::<Vec<String>>())
+            .collect::<Vec<_>>();
+        // trailing blank lines are only the end of the used area
+        while rows.last().map(|cells| cells.iter().all(|cell| cell.is_empty())).unwrap_or(false) {
+            rows.pop();
+        }
+        if rows.is_empty() {
+            return Err("the range is empty".to_string());
+        }
+        let headers = rows.remove(0);
+        Ok(Self { headers, rows })
+    }
+
+    pub fn column(&self, name: &str) -> Option<usize> {
+        self.headers.iter().position(|header| header == name)
+    }
+}
+
+/// zero based row and column of a spreadsheet reference like `AB12`
+fn parse_cell(text: &str) -> Result<(usize, usize), String> {
+    let text = text.trim().to_ascii_uppercase();
+    let split = text.find(|c: char| c.is_ascii_digit()).unwrap_or(text.len());
+    let (letters, digits) = text.split_at(split);
+    let row: usize = digits.parse().map_err(|_| format!("\"{}\" is not a cell like B3", text))?;
+    if letters.is_empty() || !letters.chars().all(|c| c.is_ascii_uppercase()) || row == 0 {
+        return Err(format!("\"{}\" is not a cell like B3", text));
+    }
+    let col = letters.bytes().fold(0, |col, c| col * 26 + (c - b'A') as usize + 1);
+    Ok((row - 1, col - 1))
+}
+
+/// spreadsheet cell as it would read in a CSV export, dates in ISO form
+fn cell_text(cell: &Data) -> String {
+    match cell {
+        Data::Empty | Data::Error(_) => String::new(),
+        // elapsed times formatted as [h]:mm are stored in days
+        Data::DateTime(time) if time.is_duration() => time.as_f64().to_string(),
+        Data::DateTime(_) | Data::DateTimeIso(_) => cell.as_datetime()
+            .map(|time| time.format("%Y-%m-%dT%H:%M:%S").to_string())
+            .unwrap_or_else(|| cell.to_string()),
+        _ => cell.to_string(),
+    }
+}
+
+/// every sheet of an .xlsx, .xls or .ods workbook as text, row and column indices matching the sheet's cells
+pub fn read_workbook(path: &Path) -> Result<Vec<(String, Vec<Vec<String>>)>, String> {
+    let mut workbook = open_workbook_auto(path).map_err(|er| er.to_string())?;
+    let mut sheets = Vec::new();
+    for name in workbook.sheet_names() {
+        let range = workbook.worksheet_range(&name).map_err(|er| format!("{}: {}", name, er))?;
+        let grid = match range.end() {
+            Some((rows, cols)) => (0..=rows).map(|row| (0..=cols).map(|col| {
+                range.get_value((row, col)).map(cell_text).unwrap_or_default()
+            }).collect()).collect(),
+            None => Vec::new(),
+        };
+        sheets.push((name, grid));
+    }
+    Ok(sheets)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum TimeFormat {
+    Minutes,
+    Hours,
+    Days,
+    /// ISO 8601 date and time, counted from an origin
+    Timestamp,
+}
+impl TimeFormat {
+    pub const ALL: [TimeFormat; 4] = [TimeFormat::Minutes, TimeFormat::Hours, TimeFormat::Days, TimeFormat::Timestamp];
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            TimeFormat::Minutes => "minutes",
+            TimeFormat::Hours => "hours",
+            TimeFormat::Days => "days",
+            TimeFormat::Timestamp => "timestamp",
+        }
+    }
+}
+
+/// `2024-03-01T08:30:00+01:00`, `2024-03-01 08:30(:00)` or a bare date
+pub fn parse_timestamp(text: &str) -> Option<NaiveDateTime> {
+    if let Ok(time) = DateTime::parse_from_rfc3339(text) {
+        return Some(time.naive_utc());
+    }
+    ["%Y-%m-%dT%H:%M:%S%.f", "%Y-%m-%d %H:%M:%S%.f", "%Y-%m-%dT%H:%M", "%Y-%m-%d %H:%M"].iter()
+        .find_map(|format| NaiveDateTime::parse_from_str(text, format).ok())
+        .or_else(|| NaiveDate::parse_from_str(text, "%Y-%m-%d").ok().and_then(|date| date.and_hms_opt(0, 0, 0)))
+}
+
+/// a number that may use a decimal comma
+fn parse_number(text: &str) -> Option<f64> {
+    text.parse().ok().or_else(|| text.replace(',', ".").parse().ok())
+}
+
+/// Units a group is commonly reported in, with the factor to the model unit first in the list
+pub fn units(group: &Group) -> &'static [(&'static str, f64)] {
+    match group {
+        Group::VCD | Group::TCD => &[("MVC/mL", 1.), ("10^5 cells/mL", 0.1), ("cells/mL", 1e-6)],
+        Group::Glucose => &[("g/L", 1.), ("mmol/L", 0.180156), ("mg/dL", 0.01)],
+        Group::Glutamin => &[("g/L", 1.), ("mmol/L", 0.146146)],
+        Group::Lactate => &[("g/L", 1.), ("mmol/L", 0.090078)],
+        Group::Ammonia => &[("g/L", 1.), ("mmol/L", 0.017031)],
+        Group::DO => &[("%", 1.)],
+        Group::Product => &[("g/L", 1.), ("mg/L", 1e-3)],
+        Group::Viability => &[("%", 1.), ("fraction", 100.)],
+    }
+}
+
+/// A file column read into a group
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ColumnMapping {
+    pub column: String,
+    pub group: Group,
+    /// label from `units`, values are multiplied by its factor
+    pub unit: String,
+}
+impl ColumnMapping {
+    pub fn factor(&self) -> f64 {
+        units(&self.group).iter().find(|(unit, _)| *unit == self.unit).map(|(_, factor)| *factor).unwrap_or(1.)
+    }
+}
+
+/// How the columns of a file become measurements, saved to reuse for the next export of the same kind
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ImportProfile {
+    pub delimiter: char,
+    /// cells read from a spreadsheet like `A1:F200`, the whole sheet when empty
+    #[serde(default)]
+    pub range: String,
+    pub time_column: String,
+    pub time_format: TimeFormat,
+    /// timestamp counted as minute zero, the earliest one in the file when empty
+    pub origin: String,
+    pub columns: Vec<ColumnMapping>,
+}
+
+/// Measurements read from a table, with every row that was left out and why
+#[derive(Debug, Clone, Default)]
+pub struct Import {
+    pub points: Vec<(Group, f64, f64)>,
+    pub skipped: Vec<(usize, String)>,
+    /// mapped columns the table does not have
+    pub missing: Vec<String>,
+}
+
+impl ImportProfile {
+    /// the column names of the original export format
+    pub fn default() -> Self {
+        let column = |column: &str, group: Group| ColumnMapping { column: column.to_string(), unit: units(&group)[0].0.to_string(), group };
+        Self {
+            delimiter: ',',
+            range: String::new(),
+            time_column: "minutes".to_string(),
+            time_format: TimeFormat::Minutes,
+            origin: String::new(),
+            columns: vec![
+                column("vcd", Group::VCD),
+                column("gln", Group::Glutamin),
+                column("gluc", Group::Glucose),
+                column("do_50", Group::DO),
+                column("product", Group::Product),
+                column("lactate", Group::Lactate),
+                column("ammonia", Group::Ammonia),
+                column("tcd", Group::TCD),
+                column("viability", Group::Viability),
+            ],
+        }
+    }
+
+    pub fn mapping(&self, column: &str) -> Option<&ColumnMapping> {
+        self.columns.iter().find(|mapping| mapping.column == column)
+    }
+
+    /// minutes since the origin of every row, or why the row has none
+    pub fn times(&self, table: &Table) -> Result<Vec<Result<f64, String>>, String> {
+        let index = table.column(&self.time_column).ok_or_else(|| format!("no time column \"{}\"", self.time_column))?;
+        let cells = table.rows.iter().map(|row| row.get(index).map(|cell| cell.as_str()).unwrap_or_default());
+        let scale = match self.time_format {
+            TimeFormat::Minutes => 1.,
+            TimeFormat::Hours => 60.,
+            TimeFormat::Days => 24. * 60.,
+            TimeFormat::Timestamp => {
+                let stamps: Vec<Option<NaiveDateTime>> = cells.clone().map(parse_timestamp).collect();
+                let origin = if self.origin.trim().is_empty() {
+                    stamps.iter().flatten().min().copied().ok_or("no readable timestamp")?
+                } else {
+                    parse_timestamp(self.origin.trim()).ok_or_else(|| format!("origin \"{}\" is not an ISO timestamp", self.origin))?
+                };
+                return Ok(cells.zip(stamps).map(|(cell, stamp)| match stamp {
+                    Some(stamp) => Ok((stamp - origin).num_milliseconds() as f64 / 60_000.),
+                    None if cell.is_empty() => Err("no time".to_string()),
+                    None => Err(format!("\"{}\" is not an ISO timestamp", cell)),
+                }).collect());
+            },
+        };
+        Ok(cells.map(|cell| match parse_number(cell) {
+            Some(t) => Ok(t * scale),
+            None if cell.is_empty() => Err("no time".to_string()),
+            None => Err(format!("\"{}\" is not a time", cell)),
+        }).collect())
+    }
+
+    /// every mapped value converted to the model units, in minutes since the origin.
+    /// Mapped columns the table lacks are listed in `missing`, so one profile serves files with fewer columns.
+    pub fn apply(&self, table: &Table) -> Result<Import, String> {
+        let mut out = Import::default();
+        let mut columns: Vec<(usize, &ColumnMapping)> = Vec::new();
+        for mapping in &self.columns {
+            match table.column(&mapping.column) {
+                Some(index) => columns.push((index, mapping)),
+                None => out.missing.push(mapping.column.clone()),
+            }
+        }
+        // row numbers count the header as line 1, like a spreadsheet
+        for (i, (row, time)) in table.rows.iter().zip(self.times(table)?).enumerate() {
+            let t = match time {
+                Ok(t) => t,
+                Err(er) => {
+                    out.skipped.push((i + 2, er));
+                    continue;
+                },
+            };
+            for (index, mapping) in &columns {
+                let cell = row.get(*index).map(|cell| cell.as_str()).unwrap_or_default();
+                if cell.is_empty() {
+                    continue;
+                }
+                match parse_number(cell) {
+                    Some(y) => out.points.push((mapping.group.clone(), t, y * mapping.factor())),
+                    None => out.skipped.push((i + 2, format!("{}: \"{}\" is not a number", mapping.column, cell))),
+                }
+            }
+        }
+        Ok(out)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn profile(time_format: TimeFormat, origin: &str, columns: &[(&str, Group, &str)]) -> ImportProfile {
+        ImportProfile {
+            delimiter: ',',
+            range: String::new(),
+            time_column: "time".to_string(),
+            time_format,
+            origin: origin.to_string(),
+            columns: columns.iter().map(|(column, group, unit)| ColumnMapping { column: column.to_string(), group: group.clone(), unit: unit.to_string() }).collect(),
+        }
+    }
+
+    fn close(a: f64, b: f64) -> bool {
+        (a - b).abs() < 1e-9
+    }
+
+    #[test]
+    fn decimal_comma_numbers() {
+        assert_eq!(parse_number("1.5"), Some(1.5));
+        assert_eq!(parse_number("1,5"), Some(1.5));
+        assert_eq!(parse_number("-0,25"), Some(-0.25));
+        assert_eq!(parse_number("1e-3"), Some(1e-3));
+        assert_eq!(parse_number("n/a"), None);
+        assert_eq!(parse_number(""), None);
+    }
+
+    #[test]
+    fn timestamps() {
+        let time = NaiveDate::from_ymd_opt(2024, 3, 1).unwrap().and_hms_opt(8, 30, 0);
+        for text in ["2024-03-01T08:30:00", "2024-03-01 08:30", "2024-03-01T09:30:00+01:00", "2024-03-01T08:30:00Z"] {
+            assert_eq!(parse_timestamp(text), time, "{}", text);
+        }
+        assert_eq!(parse_timestamp("2024-03-01"), NaiveDate::from_ymd_opt(2024, 3, 1).unwrap().and_hms_opt(0, 0, 0));
+        assert_eq!(parse_timestamp("01.03.2024"), None);
+    }
+
+    #[test]
+    fn rfc3339_and_naive_times_from_an_origin() {
+        let table = Table::from_csv("time\n2024-03-01T10:00:00Z\n2024-03-01T11:00:00+02:00\n2024-03-01 09:00\n2024-03-02T08:00\n", b',').unwrap();
+        let times: Vec<f64> = profile(TimeFormat::Timestamp, "2024-03-01T08:00", &[]).times(&table).unwrap().into_iter().map(|t| t.unwrap()).collect();
+        assert_eq!(times, vec![120., 60., 60., 1440.]);
+        // without an origin the earliest timestamp is minute zero
+        let times: Vec<f64> = profile(TimeFormat::Timestamp, "", &[]).times(&table).unwrap().into_iter().map(|t| t.unwrap()).collect();
+        assert_eq!(times, vec![60., 0., 0., 1380.]);
+        assert!(profile(TimeFormat::Timestamp, "yesterday", &[]).times(&table).is_err());
+    }
+
+    #[test]
+    fn time_units() {
+        let table = Table::from_csv("time\n0\n1,5\n", b';').unwrap();
+        let minutes = |format| profile(format, "", &[]).times(&table).unwrap().into_iter().map(|t| t.unwrap()).collect::<Vec<f64>>();
+        assert_eq!(minutes(TimeFormat::Minutes), vec![0., 1.5]);
+        assert_eq!(minutes(TimeFormat::Hours), vec![0., 90.]);
+        assert_eq!(minutes(TimeFormat::Days), vec![0., 2160.]);
+    }
+
+    #[test]
+    fn millimolar_factors() {
+        // g/L per mmol/L is the molar mass in g/mol over 1000
+        let factor = |group: Group| units(&group).iter().find(|(unit, _)| *unit == "mmol/L").map(|(_, factor)| *factor).unwrap();
+        assert!(close(factor(Group::Glucose), 180.156e-3));
+        assert!(close(factor(Group::Glutamin), 146.146e-3));
+        assert!(close(factor(Group::Lactate), 90.078e-3));
+        assert!(close(factor(Group::Ammonia), 17.031e-3));
+        // the model unit comes first
+        for group in Group::ALL {
+            assert_eq!(units(&group)[0].1, 1., "{}", group);
+        }
+    }
+
+    #[test]
+    fn apply_converts_units() {
+        let table = Table::from_csv("time;gluc;vcd\n0;10;2\n60;5,5;3\n", b';').unwrap();
+        let import = profile(TimeFormat::Minutes, "", &[("gluc", Group::Glucose, "mmol/L"), ("vcd", Group::VCD, "10^5 cells/mL")]).apply(&table).unwrap();
+        assert!(import.skipped.is_empty());
+        let expected = [(Group::Glucose, 0., 1.80156), (Group::VCD, 0., 0.2), (Group::Glucose, 60., 0.990858), (Group::VCD, 60., 0.3)];
+        assert_eq!(import.points.len(), expected.len());
+        for ((group, t, y), (want_group, want_t, want_y)) in import.points.iter().zip(expected) {
+            assert_eq!(*group, want_group);
+            assert_eq!(*t, want_t);
+            assert!(close(*y, want_y), "{} {}", y, want_y);
+        }
+    }
+
+    #[test]
+    fn skipped_rows_keep_their_line_numbers() {
+        let table = Table::from_csv("time,vcd\n0,1\n,2\n20,abc\n30,\n40,4\n", b',').unwrap();
+        let import = profile(TimeFormat::Minutes, "", &[("vcd", Group::VCD, "MVC/mL"), ("gluc", Group::Glucose, "g/L")]).apply(&table).unwrap();
+        // the header is line 1, an empty cell is no measurement rather than an error
+        assert_eq!(import.skipped, vec![(3, "no time".to_string()), (4, "vcd: \"abc\" is not a number".to_string())]);
+        assert_eq!(import.points.len(), 2);
+        assert_eq!(import.missing, vec!["gluc".to_string()]);
+    }
+
+    #[test]
+    fn cell_references() {
+        assert_eq!(parse_cell("A1"), Ok((0, 0)));
+        assert_eq!(parse_cell("B3"), Ok((2, 1)));
+        assert_eq!(parse_cell("Z1"), Ok((0, 25)));
+        assert_eq!(parse_cell("AB12"), Ok((11, 27)));
+        assert_eq!(parse_cell(" ab12 "), Ok((11, 27)));
+        for text in ["A0", "12", "A", "", "A1B", "Ä1"] {
+            assert!(parse_cell(text).is_err(), "{}", text);
+        }
+    }
+
+    fn grid() -> Vec<Vec<String>> {
+        let rows: [&[&str]; 6] = [
+            &["title", "", ""],
+            &["", "time", "vcd"],
+            &["", "0", "1"],
+            &["", "60", "2"],
+            &["", "120", "3"],
+            &["", "", ""],
+        ];
+        rows.iter().map(|row| row.iter().map(|cell| cell.to_string()).collect()).collect()
+    }
+
+    #[test]
+    fn grid_ranges() {
+        let whole = Table::from_grid(&grid(), "").unwrap();
+        assert_eq!(whole.headers, vec!["title", "", ""]);
+        // the blank last row is trimmed
+        assert_eq!(whole.rows.len(), 4);
+
+        let from = Table::from_grid(&grid(), "B2").unwrap();
+        assert_eq!(from.headers, vec!["time", "vcd"]);
+        assert_eq!(from.rows, vec![vec!["0", "1"], vec!["60", "2"], vec!["120", "3"]]);
+
+        let bounded = Table::from_grid(&grid(), "b2:C4").unwrap();
+        assert_eq!(bounded.headers, vec!["time", "vcd"]);
+        assert_eq!(bounded.rows, vec![vec!["0", "1"], vec!["60", "2"]]);
+
+        let column = Table::from_grid(&grid(), "C2:C100").unwrap();
+        assert_eq!(column.headers, vec!["vcd"]);
+        assert_eq!(column.rows.len(), 3);
+    }
+
+    #[test]
+    fn bad_grid_ranges() {
+        assert!(Table::from_grid(&grid(), "C4:B2").is_err());
+        assert!(Table::from_grid(&grid(), "A10").is_err());
+        assert!(Table::from_grid(&grid(), "B0:C4").is_err());
+        assert!(Table::from_grid(&[], "").is_err());
+    }
+
+    #[test]
+    fn missing_time_column() {
+        let table = Table::from_csv("minutes,vcd\n0,1\n", b',').unwrap();
+        assert!(profile(TimeFormat::Minutes, "", &[("vcd", Group::VCD, "MVC/mL")]).apply(&table).is_err());
+    }
+}
@@ -5,6 +5,20 @@ pub mod ui;
 pub mod model;
 pub mod regressor;
 pub mod base;
+pub mod solver;
+pub mod simulation;
+pub mod optimizer;
+pub mod cmaes;
+pub mod statistics;
+pub mod profile;
+pub mod mcmc;
+pub mod batch;
+pub mod parameter;
+pub mod sensitivity;
+pub mod uncertainty;
+pub mod ensemble;
+pub mod sweep;
+pub mod import;
 
 use eframe::egui;
 use ui::{app::BionApp, Front};
@@ -0,0 +1,388 @@
+use std::sync::{atomic::{AtomicBool, Ordering}, Arc};
+
+use nalgebra::{DMatrix, DVector};
+use rand::Rng;
+use rand_distr::StandardNormal;
+use rayon::prelude::*;
+
+use crate::{ensemble::simulate_all, parameter::Parameter, regressor::{Group, Regressor}};
+
+/// Prior belief about a target, always truncated to the target's bounds
+#[derive(Debug, Clone, PartialEq)]
+pub enum Prior {
+    Uniform,
+    Normal { mean: f64, sd: f64 },
+    LogNormal { median: f64, sigma: f64 },
+}
+impl Prior {
+    pub fn label(&self) -> &'static str {
+        match self {
+            Prior::Uniform => "Uniform",
+            Prior::Normal { .. } => "Normal",
+            Prior::LogNormal { .. } => "Log-normal",
+        }
+    }
+
+    /// log density up to a constant
+    pub fn log_density(&self, x: f64) -> f64 {
+        match *self {
+            Prior::Uniform => 0.,
+            Prior::Normal { mean, sd } => -0.5 * ((x - mean) / sd).powi(2),
+            Prior::LogNormal { median, sigma } => {
+                if x <= 0. {
+                    return f64::NEG_INFINITY;
+                }
+                -x.ln() - 0.5 * ((x / median).ln() / sigma).powi(2)
+            },
+        }
+    }
+}
+
+/// Measurement error of a group, sd = sqrt(absolute² + (relative * y)²)
+#[derive(Debug, Clone, Copy)]
+pub struct Noise {
+    pub absolute: f64,
+    pub relative: f64,
+}
+impl Noise {
+    pub fn sd(&self, y: f64) -> f64 {
+        (self.absolute.powi(2) + (self.relative * y).powi(2)).sqrt().max(1e-12)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct McmcSettings {
+    pub samples: usize,
+    pub burn_in: usize,
+    /// posterior draws simulated for the predictive bands
+    pub draws: usize,
+    pub noise: [Noise; Group::ALL.len()], // in the order of Group::ALL
+    pub priors: Vec<(&'static Parameter, Prior)>,
+}
+impl McmcSettings {
+    pub fn default() -> Self {
+        let noise = |absolute| Noise { absolute, relative: 0.05 };
+        Self {
+            samples: 3000,
+            burn_in: 1000,
+            draws: 50,
+            noise: Group::ALL.map(|group| match group {
+                Group::VCD | Group::TCD => noise(0.1),
+                Group::Glucose | Group::Product | Group::Lactate => noise(0.05),
+                Group::Glutamin => noise(0.01),
+                Group::Ammonia => noise(0.005),
+                Group::DO | Group::Viability => noise(1.),
+            }),
+            priors: Vec::new(),
+        }
+    }
+
+    pub fn prior(&self, target: &Parameter) -> Prior {
+        self.priors.iter().find(|(t, _)| *t == target).map(|(_, prior)| prior.clone()).unwrap_or(Prior::Uniform)
+    }
+
+    pub fn set_prior(&mut self, target: &'static Parameter, prior: Prior) {
+        match self.priors.iter_mut().find(|(t, _)| *t == target) {
+            Some((_, old)) => *old = prior,
+            None => self.priors.push((target, prior)),
+        }
+    }
+
+    pub fn noise(&self, group: &Group) -> Noise {
+        self.noise[group.index()]
+    }
+}
+
+/// 95% posterior-predictive band of one group on the output grid
+#[derive(Debug, Clone)]
+pub struct Band {
+    pub batch: usize,
+    pub group: Group,
+    pub time: Vec<f64>,
+    pub lower: Vec<f64>,
+    pub upper: Vec<f64>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Posterior {
+    pub labels: Vec<String>,
+    /// samples after burn-in, one row per sample
+    pub chain: Vec<Vec<f64>>,
+    pub log_posterior: Vec<f64>,
+    pub acceptance: f64,
+    pub bands: Vec<Band>,
+}
+
+/// Mean, sd and the 2.5%, 50% and 97.5% quantiles of one target
+pub struct Summary {
+    pub mean: f64,
+    pub sd: f64,
+    pub quantiles: [f64; 3],
+}
+
+impl Posterior {
+    pub fn column(&self, i: usize) -> Vec<f64> {
+        self.chain.iter().map(|sample| sample[i]).collect()
+    }
+
+    pub fn summary(&self, i: usize) -> Summary {
+        let mut values = self.column(i);
+        let n = values.len() as f64;
+        let mean = values.iter().sum::<f64>() / n;
+        let sd = (values.iter().map(|v| (v - mean).powi(2)).sum::<f64>() / (n - 1.).max(1.)).sqrt();
+        values.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        Summary { mean, sd, quantiles: [quantile(&values, 0.025), quantile(&values, 0.5), quantile(&values, 0.975)] }
+    }
+
+    /// bin centers and counts
+    pub fn histogram(&self, i: usize, bins: usize) -> (Vec<[f64; 2]>, f64) {
+        let values = self.column(i);
+        let low = values.iter().cloned().fold(f64::INFINITY, f64::min);
+        let high = values.iter().cloned().fold(f64::NEG_INFINITY, f64::max);
+        let width = ((high - low) / bins as f64).max(f64::MIN_POSITIVE);
+        let mut counts = vec![0usize; bins];
+        for v in values {
+            counts[(((v - low) / width) as usize).min(bins - 1)] += 1;
+        }
+        let bars = counts.into_iter().enumerate().map(|(k, count)| [low + (k as f64 + 0.5) * width, count as f64]).collect();
+        (bars, width)
+    }
+}
+
+/// sorted `values`, linear interpolation between order statistics
+pub fn quantile(values: &[f64], q: f64) -> f64 {
+    if values.is_empty() {
+        return f64::NAN;
+    }
+    let pos = q * (values.len() - 1) as f64;
+    let (i, w) = (pos.floor() as usize, pos.fract());
+    values[i] + (values[(i + 1).min(values.len() - 1)] - values[i]) * w
+}
+
+/// Gaussian log likelihood of the measurements plus the log priors, `-inf` outside the bounds
+pub fn log_posterior(regressor: &Regressor, settings: &McmcSettings, vals: &[f64]) -> f64 {
+    let mut log_prior = 0.;
+    for (slot, val) in regressor.slots().iter().zip(vals) {
+        let fit = regressor.slot_target(slot);
+        if *val < fit.lower || *val > fit.upper {
+            return f64::NEG_INFINITY;
+        }
+        log_prior += settings.prior(fit.target).log_density(*val);
+    }
+    let mut log_likelihood = 0.;
+    for (batch, sim) in simulate_all(&regressor.apply(vals), &regressor.settings).into_iter().enumerate() {
+        let sim = match sim {
+            Ok(sim) => sim,
+            Err(_) => return f64::NEG_INFINITY,
+        };
+        log_likelihood += regressor.pairs(batch, &sim).iter().map(|(node, y)| {
+            let sd = settings.noise(&node.group).sd(node.y);
+            -0.5 * ((y - node.y) / sd).powi(2) - sd.ln()
+        }).sum::<f64>();
+    }
+    let out = log_prior + log_likelihood;
+    if out.is_nan() { f64::NEG_INFINITY } else { out }
+}
+
+/// Adaptive Metropolis (Haario et al. 2001): a random walk whose proposal covariance
+/// follows the chain's own covariance once enough samples are in.
+pub fn sample<F>(regressor: Regressor, settings: McmcSettings, cancel: Arc<AtomicBool>, mut progress: F) -> Result<Posterior, String>
+where
+    F: FnMut(u64, f64),
+{
+    const ADAPT_AFTER: usize = 200;
+    let mut rng = rand::thread_rng();
+    let slots = regressor.slots();
+    let d = slots.len();
+    if d == 0 {
+        return Err("no targets to sample".to_string());
+    }
+    let scale = 2.38f64.powi(2) / d as f64;
+
+    let mut current = DVector::from_vec(regressor.start());
+    let mut current_lp = log_posterior(&regressor, &settings, current.as_slice());
+    if !current_lp.is_finite() {
+        return Err("the starting point has zero posterior density".to_string());
+    }
+    let initial_sd = DVector::from_iterator(d, slots.iter().zip(current.iter()).map(|(slot, x)| {
+        let fit = regressor.slot_target(slot);
+        (0.05 * x.abs()).max(1e-3 * (fit.upper - fit.lower).abs()).max(1e-12)
+    }));
+
+    // running mean and scatter matrix of everything visited
+    let mut mean = current.clone();
+    let mut scatter = DMatrix::<f64>::zeros(d, d);
+    let mut visited = 1usize;
+
+    let mut chain = Vec::with_capacity(settings.samples);
+    let mut log_posteriors = Vec::with_capacity(settings.samples);
+    let mut accepted = 0usize;
+    let total = settings.burn_in + settings.samples;
+
+    for i in 0..total {
+        if cancel.load(Ordering::Relaxed) {
+            return Err("Cancelled".to_string());
+        }
+
+        let z = DVector::from_iterator(d, (0..d).map(|_| rng.sample::<f64, _>(StandardNormal)));
+        let step = if visited > ADAPT_AFTER {
+            let covariance = &scatter / (visited - 1) as f64 * scale + DMatrix::from_diagonal(&initial_sd.map(|sd| (1e-6 * sd).powi(2)));
+            match covariance.cholesky() {
+                Some(cholesky) => cholesky.l() * z,
+                None => initial_sd.component_mul(&z),
+            }
+        } else {
+            initial_sd.component_mul(&z)
+        };
+
+        let proposal = &current + step;
+        let proposal_lp = log_posterior(&regressor, &settings, proposal.as_slice());
+        if proposal_lp - current_lp > rng.gen::<f64>().ln() {
+            current = proposal;
+            current_lp = proposal_lp;
+            if i >= settings.burn_in {
+                accepted += 1;
+            }
+        }
+
+        visited += 1;
+        let delta = &current - &mean;
+        mean += &delta / visited as f64;
+        scatter += &delta * (&current - &mean).transpose();
+
+        if i >= settings.burn_in {
+            chain.push(current.as_slice().to_vec());
+            log_posteriors.push(current_lp);
+        }
+        if i % 10 == 0 {
+            progress(i as u64, current_lp);
+        }
+    }
+
+    let bands = predictive_bands(&regressor, &settings, &chain, &mut rng);
+    Ok(Posterior {
+        labels: slots.iter().map(|slot| regressor.slot_label(slot)).collect(),
+        acceptance: accepted as f64 / settings.samples.max(1) as f64,
+        chain,
+        log_posterior: log_posteriors,
+        bands,
+    })
+}
+
+/// simulates evenly spaced posterior samples and adds measurement noise
+fn predictive_bands(regressor: &Regressor, settings: &McmcSettings, chain: &[Vec<f64>], rng: &mut impl Rng) -> Vec<Band> {
+    if chain.is_empty() || settings.draws == 0 {
+        return Vec::new();
+    }
+    let stride = (chain.len() / settings.draws).max(1);
+    let samples: Vec<&Vec<f64>> = chain.iter().step_by(stride).collect();
+    let draws: Vec<Vec<_>> = samples.par_iter()
+        .filter_map(|sample| simulate_all(&regressor.apply(sample), &regressor.settings).into_iter().collect::<Result<Vec<_>, _>>().ok())
+        .collect();
+
+    let mut bands = Vec::new();
+    for (batch, data) in regressor.batches.iter().enumerate() {
+        let results: Vec<_> = draws.iter().map(|draw| &draw[batch]).collect();
+        let time = match results.first() {
+            Some(first) => first.time.clone(),
+            None => return Vec::new(),
+        };
+        let results: Vec<_> = results.into_iter().filter(|res| res.time.len() == time.len()).collect();
+
+        for group in Group::ALL {
+            if !data.nodes.iter().any(|node| node.group == group) {
+                continue;
+            }
+            let noise = settings.noise(&group);
+            let mut lower = Vec::with_capacity(time.len());
+            let mut upper = Vec::with_capacity(time.len());
+            for k in 0..time.len() {
+                let mut values: Vec<f64> = results.iter().map(|res| {
+                    let y = res.group(&group)[k];
+                    y + noise.sd(y) * rng.sample::<f64, _>(StandardNormal)
+                }).collect();
+                values.sort_by(|a, b| a.partial_cmp(b).unwrap_or(std::cmp::Ordering::Equal));
+                lower.push(quantile(&values, 0.025));
+                upper.push(quantile(&values, 0.975));
+            }
+            bands.push(Band { batch, group, time: time.clone(), lower, upper });
+        }
+    }
+    bands
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{model::Bioreactor, regressor::{Param, RegressorNode}, simulation::{simulate, SimSettings}};
+
+    use super::*;
+
+    #[test]
+    fn quantile_interpolates_order_statistics() {
+        let values = [1., 2., 3., 4., 5.];
+        assert_eq!(quantile(&values, 0.), 1.);
+        assert_eq!(quantile(&values, 0.5), 3.);
+        assert_eq!(quantile(&values, 1.), 5.);
+        assert!((quantile(&values, 0.1) - 1.4).abs() < 1e-12);
+        assert_eq!(quantile(&[7.], 0.975), 7.);
+        assert!(quantile(&[], 0.5).is_nan());
+    }
+
+    #[test]
+    fn prior_densities() {
+        let normal = Prior::Normal { mean: 1., sd: 2. };
+        assert_eq!(normal.log_density(1.) - normal.log_density(5.), 2.);
+        let log_normal = Prior::LogNormal { median: 2., sigma: 0.5 };
+        assert_eq!(log_normal.log_density(0.), f64::NEG_INFINITY);
+        assert!((log_normal.log_density(2.) + 2f64.ln()).abs() < 1e-12);
+        assert_eq!(Prior::Uniform.log_density(-3.), 0.);
+    }
+
+    #[test]
+    fn noise_combines_absolute_and_relative() {
+        let noise = Noise { absolute: 3., relative: 0.5 };
+        assert_eq!(noise.sd(8.), 5.);
+        assert_eq!(Noise { absolute: 0., relative: 0. }.sd(1.), 1e-12);
+    }
+
+    fn regressor() -> Regressor {
+        let nodes = vec![
+            RegressorNode { group: Group::VCD, x: 0., y: 0.6 },
+            RegressorNode { group: Group::VCD, x: 600., y: 0.7 },
+            RegressorNode { group: Group::VCD, x: 1200., y: 0.8 },
+        ];
+        let settings = SimSettings { duration: 1., output_interval: 10. };
+        Regressor::single(nodes, Bioreactor::default(), Param::default(), settings)
+    }
+
+    #[test]
+    fn log_posterior_is_the_gaussian_likelihood() {
+        let regressor = regressor();
+        let mut settings = McmcSettings::default();
+        settings.noise[Group::VCD.index()] = Noise { absolute: 0.1, relative: 0. };
+        let mu_max = 4e-4;
+
+        let sim = simulate(&regressor.apply(&[mu_max])[0], &regressor.settings).unwrap();
+        let expected: f64 = regressor.batches[0].nodes.iter().map(|node| {
+            let y = sim.sample(&sim.vcd, node.x).unwrap();
+            -0.5 * ((y - node.y) / 0.1).powi(2) - 0.1f64.ln()
+        }).sum();
+        let uniform = log_posterior(&regressor, &settings, &[mu_max]);
+        assert!((uniform - expected).abs() < 1e-9, "{} != {}", uniform, expected);
+
+        // the prior adds its log density
+        let target = regressor.param.targets[0].target;
+        settings.set_prior(target, Prior::Normal { mean: 5e-4, sd: 1e-4 });
+        let with_prior = log_posterior(&regressor, &settings, &[mu_max]);
+        assert!((with_prior - uniform + 0.5).abs() < 1e-9);
+    }
+
+    #[test]
+    fn log_posterior_outside_the_bounds() {
+        let regressor = regressor();
+        let fit = &regressor.param.targets[0];
+        let settings = McmcSettings::default();
+        assert_eq!(log_posterior(&regressor, &settings, &[fit.upper * 2.]), f64::NEG_INFINITY);
+        assert_eq!(log_posterior(&regressor, &settings, &[fit.lower - 1.]), f64::NEG_INFINITY);
+    }
+}
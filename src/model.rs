@@ -1,16 +1,17 @@
-use egui::Slider;
+use egui::{RichText, Slider};
 use serde::{Deserialize, Serialize};
 
-use crate::regressor::Param;
+use crate::solver::Solver;
 
 
 pub const FEED_RATE: f64 = 0.03;
 pub const VOLUME: f64 = 45.; // L
-pub type State = ode_solvers::SVector<f64,7>;
+pub const STATES: usize = 14;
+pub type State = ode_solvers::SVector<f64,STATES>;
 pub type Time = f64;
 
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct TempShift {
     pub n_vcd: f64, // temp shift post
     pub day: f64, // day of shift
@@ -24,14 +25,26 @@ impl TempShift {
         }
     }
 }
-#[derive(Debug, Clone, Serialize, Deserialize)]
+// files saved before a field existed take it from `default()`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default = "Constants::default")]
 pub struct Constants {
     pub product: f64,   // [ml/(MVC min)]
     pub k_glucose: f64,   // [1/min]
     pub k_glutamine: f64, // [1/min]
-    pub kP: f64,        // []
     pub kDO: f64,       // [%]
 
+    pub y_lactate_glucose: f64, // [g lactate / g glucose]
+    pub k_lactate: f64,   // lactate uptake [1/min]
+    pub ks_lactate: f64,  // [g/L]
+    pub lactate_shift: f64, // glucose below which lactate is consumed [g/L]
+    pub y_ammonia_glutamine: f64, // [g ammonia / g glutamine]
+    pub ki_lactate: f64,  // growth inhibition [g/L]
+    pub ki_ammonia: f64,  // growth inhibition [g/L]
+
+    /// DO gain of files saved before it moved to `Pid::kp`, see `Bioreactor::upgrade`
+    #[serde(rename = "kP", skip_serializing)]
+    pub legacy_kp: Option<f64>,
 }
 impl Constants {
     pub fn default() -> Self {
@@ -39,18 +52,75 @@ impl Constants {
             product:    1e-4,
             k_glucose:    1e-4,
             k_glutamine:  1e-4,
-            kP:         1e-2,
             kDO:        1e-4,
+
+            y_lactate_glucose:  0.9,
+            k_lactate:          5e-5,
+            ks_lactate:         0.5,
+            lactate_shift:      2.,
+            y_ammonia_glutamine: 0.12,
+            ki_lactate:         4.,
+            ki_ammonia:         0.5,
+
+            legacy_kp:          None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum DeathKinetics {
+    Constant,
+    SubstrateLimited,
+    Toxicity,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Death {
+    pub kinetics: DeathKinetics,
+    pub k_d: f64,         // [1/min]
+    pub ks_death: f64,    // substrate limitation [g/L]
+    pub k_tox_lactate: f64, // [g/L]
+    pub k_tox_ammonia: f64, // [g/L]
+    pub lysis: bool,
+    pub k_lysis: f64,     // [1/min]
+}
+impl Death {
+    pub fn default() -> Self {
+        Self {
+            kinetics: DeathKinetics::Constant,
+            k_d: 5e-6,
+            ks_death: 0.1,
+            k_tox_lactate: 4.,
+            k_tox_ammonia: 0.5,
+            lysis: false,
+            k_lysis: 1e-5,
+        }
+    }
+
+    /// specific death rate [1/min]
+    pub fn rate(&self, gluc: f64, glut: f64, lactate: f64, ammonia: f64) -> f64 {
+        match self.kinetics {
+            DeathKinetics::Constant => self.k_d,
+            DeathKinetics::SubstrateLimited => {
+                let substrate = gluc.min(glut).max(0.);
+                self.k_d * ( self.ks_death / ( self.ks_death + substrate ) )
+            },
+            DeathKinetics::Toxicity => {
+                let (lactate, ammonia) = (lactate.max(0.), ammonia.max(0.));
+                self.k_d * ( lactate / ( self.k_tox_lactate + lactate ) + ammonia / ( self.k_tox_ammonia + ammonia ) )
+            },
         }
     }
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct Airation {
     pub cell_metabolism: f64,
     pub air_flow: f64, // [VVh]
     pub henry: f64,
-    pub pid: Pid
+    pub pid: Pid,
+    #[serde(default = "DoControlStrategy::default")]
+    pub strategy: DoControlStrategy,
 }
 impl Airation{
     pub fn default() -> Self {
@@ -59,13 +129,158 @@ impl Airation{
             air_flow: 5e-3,
             henry: 1.6e-3,
             pid: Pid::default(),
+            strategy: DoControlStrategy::default(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum Actuator {
+    Agitation,
+    Air,
+    Oxygen,
+}
+impl Actuator {
+    pub const ALL: [Actuator; 3] = [Actuator::Agitation, Actuator::Air, Actuator::Oxygen];
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            Actuator::Agitation => "Agitation [W/m3]",
+            Actuator::Air => "Air flow [VVh]",
+            Actuator::Oxygen => "O2 flow [L/min]",
+        }
+    }
+    fn range(&self) -> std::ops::RangeInclusive<f64> {
+        match self {
+            Actuator::Agitation => 0.0..=200.,
+            Actuator::Air => 0.0..=10.,
+            Actuator::Oxygen => 0.0..=100.,
+        }
+    }
+    /// position in the array returned by `Bioreactor::actuators`
+    fn index(&self) -> usize {
+        match self {
+            Actuator::Agitation => 0,
+            Actuator::Air => 1,
+            Actuator::Oxygen => 2,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CascadeStage {
+    pub actuator: Actuator,
+    pub min: f64,
+    pub max: f64,
+    pub start: f64, // controller output where the stage starts to open [%]
+    pub end: f64,   // controller output where the stage is fully open [%]
+}
+impl CascadeStage {
+    pub fn new(actuator: Actuator, min: f64, max: f64, start: f64, end: f64) -> Self {
+        Self { actuator, min, max, start, end }
+    }
+
+    pub fn value(&self, output: f64) -> f64 {
+        let fraction = if self.end > self.start {
+            ((output - self.start) / (self.end - self.start)).clamp(0., 1.)
+        } else if output >= self.start {
+            1.
+        } else {
+            0.
+        };
+        self.min + (self.max - self.min) * fraction
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum DoControlStrategy {
+    /// agitation and air are fixed, the controller only adds pure O2
+    OxygenOnly,
+    /// stages are opened one after another as the controller output rises
+    Cascade(Vec<CascadeStage>),
+}
+impl DoControlStrategy {
+    /// the plain PID on pure O2
+    pub fn default() -> Self {
+        DoControlStrategy::OxygenOnly
+    }
+
+    pub fn cascade() -> Self {
+        DoControlStrategy::Cascade(vec![
+            CascadeStage::new(Actuator::Agitation, 20., 60., 0., 33.),
+            CascadeStage::new(Actuator::Air, 5e-3, 2e-2, 33., 66.),
+            CascadeStage::new(Actuator::Oxygen, 0., 15., 66., 100.),
+        ])
+    }
+
+    pub fn view(&mut self, ui: &mut egui::Ui) -> bool {
+        let mut changed = false;
+        ui.horizontal(|ui| {
+            let cascade = matches!(self, DoControlStrategy::Cascade(_));
+            if ui.selectable_label(!cascade, "O2 only").clicked() && cascade {
+                *self = DoControlStrategy::OxygenOnly;
+                changed = true;
+            }
+            if ui.selectable_label(cascade, "Cascade").clicked() && !cascade {
+                *self = DoControlStrategy::cascade();
+                changed = true;
+            }
+        });
+
+        if let DoControlStrategy::Cascade(stages) = self {
+            let mut move_up = None;
+            let mut delete = None;
+            for (i, stage) in stages.iter_mut().enumerate() {
+                ui.separator();
+                ui.horizontal(|ui| {
+                    ui.label(format!("{}.", i + 1));
+                    egui::ComboBox::from_id_source(("cascade_stage", i))
+                        .selected_text(stage.actuator.label())
+                        .show_ui(ui, |ui| {
+                            for actuator in Actuator::ALL {
+                                changed |= ui.selectable_value(&mut stage.actuator, actuator, actuator.label()).changed();
+                            }
+                        });
+                    if i > 0 && ui.button("Up").clicked() {
+                        move_up = Some(i);
+                    }
+                    if ui.button(RichText::new("Delete").color(ui.visuals().warn_fg_color)).clicked() {
+                        delete = Some(i);
+                    }
+                });
+                let range = stage.actuator.range();
+                changed |= ui.add(Slider::new(&mut stage.min, range.clone()).text("min")).changed();
+                changed |= ui.add(Slider::new(&mut stage.max, range).text("max")).changed();
+                changed |= ui.add(Slider::new(&mut stage.start, 0.0..=100.).text("from output [%]")).changed();
+                changed |= ui.add(Slider::new(&mut stage.end, 0.0..=100.).text("to output [%]")).changed();
+            }
+            if let Some(i) = move_up {
+                stages.swap(i - 1, i);
+                changed = true;
+            }
+            if let Some(i) = delete {
+                stages.remove(i);
+                changed = true;
+            }
+            ui.separator();
+            if ui.button(RichText::new("+")).clicked() {
+                stages.push(CascadeStage::new(Actuator::Oxygen, 0., 15., 0., 100.));
+                changed = true;
+            }
         }
+        changed
     }
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default = "Pid::default")]
 pub struct Pid {
-    pub minimum: f64,
+    pub minimum: f64, // DO setpoint [%]
+    pub kp: f64,      // [1/%]
+    pub ki: f64,      // [1/(% min)]
+    pub kd: f64,      // [min/%]
+    pub derivative_filter: f64, // [min]
+    pub actuator_lag: f64,      // [min]
     pub fi_oxygen_max: f64,
     pub max_flow: f64,
 }
@@ -73,19 +288,33 @@ impl Pid {
     pub fn default() -> Self {
         Self {
             minimum: 25.,
+            kp: 1e-2,
+            ki: 1e-5,
+            kd: 0.,
+            derivative_filter: 5.,
+            actuator_lag: 5.,
             fi_oxygen_max: 15.,
             max_flow: 15.,
         }
     }
+
+    /// O2 flow requested by the controller before clamping [L/min]
+    pub fn demand(&self, error: f64, integral: f64, derivative: f64) -> f64 {
+        ( self.kp * error + self.ki * integral + self.kd * derivative ) * self.fi_oxygen_max * 1000.
+    }
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default = "Initial::default")]
 pub struct Initial {
     pub volume: f64,
     pub vcd: f64,
     pub glucose: f64,
     pub glutamine: f64,
     pub oxygen_part: f64,
+    pub lactate: f64,
+    pub ammonia: f64,
+    pub viability: f64, // [%]
 }
 impl Initial {
     pub fn default() -> Self {
@@ -95,11 +324,23 @@ impl Initial {
             glucose: 12.,
             glutamine: 7.,
             oxygen_part: 80.,
+            lactate: 0.2,
+            ammonia: 0.,
+            viability: 98.,
+        }
+    }
+
+    /// dead cell density matching the initial viability [MVC/mL]
+    pub fn dead(&self) -> f64 {
+        if self.viability > 0. {
+            self.vcd * ( 100. / self.viability - 1. )
+        } else {
+            0.
         }
     }
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct Feeding {
     pub start : f64, // [day]
     pub rate: f64, // [(%"IWV")/"day]
@@ -117,7 +358,7 @@ impl Feeding {
     }
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct Bioreactor {
     pub mu_max: f64,
     pub power_input: f64, // pzv
@@ -126,10 +367,14 @@ pub struct Bioreactor {
     pub ks_glutamine: f64,
 
     pub temp_shift: TempShift,
+    #[serde(default = "Death::default")]
+    pub death: Death,
     pub constants: Constants,
     pub airation: Airation,
     pub initial: Initial,
     pub feeding: Feeding,
+    #[serde(default = "Solver::default")]
+    pub solver: Solver,
 }
 impl Bioreactor {
 
@@ -141,10 +386,19 @@ impl Bioreactor {
             ks_glutamine: 0.05,
 
             temp_shift: TempShift::default(),
+            death: Death::default(),
             constants: Constants::default(),
             airation: Airation::default(),
             initial: Initial::default(),
             feeding: Feeding::default(),
+            solver: Solver::default(),
+        }
+    }
+
+    /// moves values loaded from older files to where they live now
+    pub fn upgrade(&mut self) {
+        if let Some(kp) = self.constants.legacy_kp.take() {
+            self.airation.pid.kp = kp;
         }
     }
 
@@ -155,6 +409,42 @@ impl Bioreactor {
         self.airation.henry * 0.21
     }
 
+    pub fn initial_state(&self) -> State {
+        let actuators = self.actuators(0.);
+        State::from([
+            self.initial.volume,
+            self.initial.vcd,
+            self.initial.glucose,
+            self.initial.glutamine,
+            (self.initial.oxygen_part * self.oxigen_saturation()) / 100.,
+            actuators[2],
+            0.,
+            self.initial.lactate,
+            self.initial.ammonia,
+            self.initial.dead(),
+            0.,
+            self.initial.oxygen_part,
+            actuators[0],
+            actuators[1],
+        ])
+    }
+
+    /// agitation [W/m3], air flow [VVh] and O2 flow [L/min] set by the DO controller output [%]
+    pub fn actuators(&self, output: f64) -> [f64; 3] {
+        let mut out = [self.power_input, self.airation.air_flow, 0.];
+        match &self.airation.strategy {
+            DoControlStrategy::OxygenOnly => {
+                out[2] = output / 100. * self.airation.pid.max_flow;
+            },
+            DoControlStrategy::Cascade(stages) => {
+                for stage in stages {
+                    out[stage.actuator.index()] = stage.value(output);
+                }
+            },
+        }
+        out
+    }
+
     pub fn fit(mu_max: f64, feed_rate: f64, air_flow: f64, k_gluc: f64,k_glut: f64 ) -> Self {
 
         let mut def = Self::default();
@@ -180,6 +470,9 @@ impl Bioreactor {
             ui.add(Slider::new(&mut self.initial.glucose, 0.0..=20.).text("glucose [g/L]")).changed() ||
             ui.add(Slider::new(&mut self.initial.glutamine, 0.0..=20.).text("glutamine [g/L]")).changed() ||
             ui.add(Slider::new(&mut self.initial.oxygen_part, 0.0..=100.).text("oxigen part [%]")).changed() ||
+            ui.add(Slider::new(&mut self.initial.lactate, 0.0..=10.).text("lactate [g/L]")).changed() ||
+            ui.add(Slider::new(&mut self.initial.ammonia, 0.0..=5.).text("ammonia [g/L]")).changed() ||
+            ui.add(Slider::new(&mut self.initial.viability, 1.0..=100.).text("viability [%]")).changed() ||
             false
         }).body_returned.unwrap_or(false) ||
         ui.collapsing("Constants", |ui|{
@@ -187,8 +480,20 @@ impl Bioreactor {
             ui.add(Slider::new(&mut self.constants.k_glucose, 0.0..=0.001).text("glucose [MVC/min]")).changed() ||
             ui.add(Slider::new(&mut self.constants.k_glutamine, 0.0..=0.001).text("glutamine [MVC/min]")).changed() ||
             ui.add(Slider::new(&mut self.constants.kDO, 0.0..=0.001).text("kDO [mol/L]")).changed() ||
-            ui.add(Slider::new(&mut self.constants.kP, 0.0..=1.).text("kP [/]")).changed() ||
             ui.add(Slider::new(&mut self.airation.henry,0.0..=10.).text("Henry's constant [mol/(bar L)]")).changed() ||
+            ui.collapsing("Lactate", |ui| {
+                ui.add(Slider::new(&mut self.constants.y_lactate_glucose, 0.0..=2.).text("yield from glucose [g/g]")).changed() ||
+                ui.add(Slider::new(&mut self.constants.k_lactate, 0.0..=0.001).text("uptake [1/min]")).changed() ||
+                ui.add(Slider::new(&mut self.constants.ks_lactate, 0.0..=5.).text("ks lactate [g/L]")).changed() ||
+                ui.add(Slider::new(&mut self.constants.lactate_shift, 0.0..=10.).text("shift below glucose [g/L]")).changed() ||
+                ui.add(Slider::new(&mut self.constants.ki_lactate, 0.01..=50.).text("inhibition [g/L]")).changed() ||
+                false
+            }).body_returned.unwrap_or(false) ||
+            ui.collapsing("Ammonia", |ui| {
+                ui.add(Slider::new(&mut self.constants.y_ammonia_glutamine, 0.0..=1.).text("yield from glutamine [g/g]")).changed() ||
+                ui.add(Slider::new(&mut self.constants.ki_ammonia, 0.01..=10.).text("inhibition [g/L]")).changed() ||
+                false
+            }).body_returned.unwrap_or(false) ||
             false
         }).body_returned.unwrap_or(false) ||
         ui.label("Time shift").changed() ||
@@ -198,15 +503,44 @@ impl Bioreactor {
             false
 
         }).body_returned.unwrap_or(false) ||
+        ui.collapsing("Death", |ui| {
+            ui.horizontal(|ui| {
+                ui.selectable_value(&mut self.death.kinetics, DeathKinetics::Constant, "Constant").changed() |
+                ui.selectable_value(&mut self.death.kinetics, DeathKinetics::SubstrateLimited, "Substrate limited").changed() |
+                ui.selectable_value(&mut self.death.kinetics, DeathKinetics::Toxicity, "Toxicity").changed()
+            }).inner ||
+            ui.add(Slider::new(&mut self.death.k_d, 0.0..=0.0001).text("kd [1/min]")).changed() ||
+            match self.death.kinetics {
+                DeathKinetics::Constant => false,
+                DeathKinetics::SubstrateLimited => {
+                    ui.add(Slider::new(&mut self.death.ks_death, 0.0..=2.).text("ks death [g/L]")).changed()
+                },
+                DeathKinetics::Toxicity => {
+                    ui.add(Slider::new(&mut self.death.k_tox_lactate, 0.01..=20.).text("lactate toxicity [g/L]")).changed() ||
+                    ui.add(Slider::new(&mut self.death.k_tox_ammonia, 0.01..=10.).text("ammonia toxicity [g/L]")).changed()
+                },
+            } ||
+            ui.checkbox(&mut self.death.lysis, "lysis").changed() ||
+            (self.death.lysis && ui.add(Slider::new(&mut self.death.k_lysis, 0.0..=0.0001).text("k lysis [1/min]")).changed()) ||
+            false
+        }).body_returned.unwrap_or(false) ||
         ui.collapsing("Airation", |ui|{
             ui.add(Slider::new(&mut self.airation.cell_metabolism, 0.0..=100.).text("Cell metabolism [mol / (cell min)]")).changed() ||
             ui.add(Slider::new(&mut self.airation.air_flow, 0.0..=10.).text("Air flow [L / min]")).changed() ||
             ui.collapsing("PID", |ui|{
                 ui.add(Slider::new(&mut self.airation.pid.minimum, 0.0..=100.).text("minimum [%]")).changed() ||
-                ui.add(Slider::new(&mut self.airation.pid.max_flow, 0.0..=100.).text("max_flow [L/min]")).changed() ||
+                ui.add(Slider::new(&mut self.airation.pid.kp, 0.0..=1.).text("kP [1/%]")).changed() ||
+                ui.add(Slider::new(&mut self.airation.pid.ki, 0.0..=0.001).text("kI [1/(% min)]")).changed() ||
+                ui.add(Slider::new(&mut self.airation.pid.kd, 0.0..=1.).text("kD [min/%]")).changed() ||
+                ui.add(Slider::new(&mut self.airation.pid.derivative_filter, 0.1..=60.).text("derivative filter [min]")).changed() ||
+                ui.add(Slider::new(&mut self.airation.pid.actuator_lag, 0.1..=60.).text("actuator lag [min]")).changed() ||
+                ui.add(Slider::new(&mut self.airation.pid.max_flow, 0.1..=100.).text("max_flow [L/min]")).changed() ||
                 ui.add(Slider::new(&mut self.airation.pid.fi_oxygen_max, 0.0..=100.).text("fi_oxigen_max [L / min]")).changed() ||
                 false
             }).body_returned.unwrap_or(false) ||
+            ui.collapsing("DO control", |ui| {
+                self.airation.strategy.view(ui)
+            }).body_returned.unwrap_or(false) ||
             false
         }).body_returned.unwrap_or(false) ||
         ui.collapsing("Feeding", |ui|{
@@ -216,31 +550,24 @@ impl Bioreactor {
             ui.add(Slider::new(&mut self.feeding.glutamine, 0.0..=100.).text("glutamine")).changed() ||
             false
         }).body_returned.unwrap_or(false) ||
+        ui.collapsing("Solver", |ui| {
+            self.solver.view(ui)
+        }).body_returned.unwrap_or(false) ||
         false
     }
-
-
-    pub fn update(&mut self, param: &Param, val: f64) {
-
-        match param.target {
-            crate::regressor::Target::MuMax => self.mu_max = val,
-            crate::regressor::Target::NVcd => self.temp_shift.n_vcd = val,
-            crate::regressor::Target::FeedRate => self.feeding.rate = val,
-            crate::regressor::Target::Glucose => self.constants.k_glucose = val,
-            crate::regressor::Target::Glutamin => self.constants.k_glutamine = val,
-            crate::regressor::Target::Product => self.constants.product = val,
-            crate::regressor::Target::DO => self.constants.kDO = val,
-        }
-    }
 }
 impl ode_solvers::System<Time, State> for Bioreactor {
 
     fn mut_system(&self, x: Time, y: &mut State, dy: &mut State) {
-        let (v, vcd, gluc, glut, c_o2, mut o2_flow, product) = (y[0], y[1], y[2], y[3], y[4], y[5], y[6]);
+        self.system(x, y, dy)
+    }
+    fn system(&self, x: Time, y: &State, dy: &mut State) {
+        let (v, vcd, gluc, glut, c_o2, o2_flow, product, lactate, ammonia, dead) = (y[0], y[1], y[2], y[3], y[4], y[5], y[6], y[7], y[8], y[9]);
+        let (integral, do_filtered) = (y[10], y[11]);
 
-        let power_input = self.power_input; // [W/m^3]
+        let power_input = y[12]; // [W/m^3]
         
-        let air_flow = self.airation.air_flow * self.initial.volume; // [L/min]
+        let air_flow = y[13] * self.initial.volume; // [L/min]
 
         
         // Temp shift
@@ -253,14 +580,36 @@ impl ode_solvers::System<Time, State> for Bioreactor {
         //dy[0] = 
 
         // VCD
-        let mut c_mu = self.mu_max * ( gluc / (self.ks_glucose + gluc)) * ( glut / ( self.ks_glutamine + glut ) ) * (c_o2 / ( self.constants.kDO + c_o2));
+        // c_O2 can dip below zero while the DO actuators catch up, the Monod term would blow up at -kDO
+        let c_o2_monod = c_o2.max(0.);
+        let mut c_mu = self.mu_max * ( gluc / (self.ks_glucose + gluc)) * ( glut / ( self.ks_glutamine + glut ) ) * (c_o2_monod / ( self.constants.kDO + c_o2_monod));
+        // inhibition by metabolic by-products
+        c_mu *= ( self.constants.ki_lactate / ( self.constants.ki_lactate + lactate.max(0.) ) ) * ( self.constants.ki_ammonia / ( self.constants.ki_ammonia + ammonia.max(0.) ) );
         c_mu = if gluc < 0. || glut < 0. || c_o2 < 0. {-1. * c_mu.abs()} else {c_mu}; // old -1. * c_mu.abs()
         
-        dy[1] = c_mu * vcd * n_vcd;
+        // Death
+        let k_d = self.death.rate(gluc, glut, lactate, ammonia);
+
+        dy[1] = c_mu * vcd * n_vcd - k_d * vcd;
+        dy[9] = k_d * vcd;
+        if self.death.lysis {
+            dy[9] -= self.death.k_lysis * dead;
+        }
         // Gluc
-        dy[2] = - self.constants.k_glucose * vcd * ( gluc / ( self.ks_glucose + gluc) );
+        let q_gluc = self.constants.k_glucose * vcd * ( gluc / ( self.ks_glucose + gluc) );
+        dy[2] = - q_gluc;
         // Glut
-        dy[3] = - self.constants.k_glutamine * vcd * ( glut / ( self.ks_glutamine + glut) );
+        let q_glut = self.constants.k_glutamine * vcd * ( glut / ( self.ks_glutamine + glut) );
+        dy[3] = - q_glut;
+
+        // Lactate, produced from glucose until the metabolic shift, consumed after
+        dy[7] = if gluc > self.constants.lactate_shift {
+            self.constants.y_lactate_glucose * q_gluc
+        } else {
+            - self.constants.k_lactate * vcd * ( lactate.max(0.) / ( self.constants.ks_lactate + lactate.max(0.) ) )
+        };
+        // Ammonia
+        dy[8] = self.constants.y_ammonia_glutamine * q_glut;
 
         // PRODUCT
 
@@ -270,17 +619,24 @@ impl ode_solvers::System<Time, State> for Bioreactor {
         let DO = (c_o2 / self.oxigen_saturation()) * 100.; // za PiD parametre
         #[allow(non_snake_case)]
         let DO_error = self.airation.pid.minimum - DO;
-        let mv = self.constants.kP * DO_error;
-        if DO_error > 0. {
-            o2_flow = (mv * self.airation.pid.fi_oxygen_max) * 1000.;
-        if o2_flow > self.airation.pid.max_flow {
-            o2_flow = self.airation.pid.max_flow;
-        }
-        } else {
-            o2_flow = 0.;
-        }
-        
-        y[5] = o2_flow;
+        let pid = &self.airation.pid;
+
+        // derivative on the filtered measurement, so setpoint changes don't kick the output
+        let do_rate = (DO - do_filtered) / pid.derivative_filter;
+        let demand = pid.demand(DO_error, integral, -do_rate);
+        // controller output as a share of full scale [%]
+        let output = (100. * demand / pid.max_flow).clamp(0., 100.);
+
+        // anti-windup, the integral is held while the output is saturated in the direction of the error
+        let saturated = (output >= 100. && DO_error > 0.) || (output <= 0. && DO_error < 0.);
+        dy[10] = if saturated { 0. } else { DO_error };
+        dy[11] = do_rate;
+
+        // actuators follow the controller output with a first order lag
+        let [power_target, air_target, o2_target] = self.actuators(output);
+        dy[5] = (o2_target - o2_flow) / pid.actuator_lag;
+        dy[12] = (power_target - y[12]) / pid.actuator_lag;
+        dy[13] = (air_target - y[13]) / pid.actuator_lag;
 
 
         let flow_total = air_flow + o2_flow; // 
@@ -320,7 +676,31 @@ impl ode_solvers::System<Time, State> for Bioreactor {
             dy[3] += ( self.feeding.glutamine - glut ) * ( fi_v / v );
 
             dy[6] -= product * (fi_v / v);
+            dy[7] -= lactate * (fi_v / v);
+            dy[8] -= ammonia * (fi_v / v);
+            dy[9] -= dead * (fi_v / v);
         }
     }
-    fn system(&self, x: Time, y: &State, dy: &mut State) {}
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn upgrade_keeps_the_saved_do_gain() {
+        let mut json = serde_json::to_value(Bioreactor::default()).unwrap();
+        assert!(json["constants"].get("kP").is_none());
+        json["constants"]["kP"] = 0.3.into();
+        // older files had no gains in the controller
+        for gain in ["kp", "ki", "kd"] {
+            json["airation"]["pid"].as_object_mut().unwrap().remove(gain);
+        }
+
+        let mut sim: Bioreactor = serde_json::from_value(json).unwrap();
+        sim.upgrade();
+        assert_eq!(sim.airation.pid.kp, 0.3);
+        assert_eq!(sim.constants.legacy_kp, None);
+        assert_eq!(sim.airation.pid.ki, Pid::default().ki);
+    }
 }
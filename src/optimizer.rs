@@ -0,0 +1,167 @@
+use std::sync::{atomic::AtomicBool, Arc};
+
+use argmin::{core::{observers::ObserverMode, Error, Executor, State as SolverState}, solver::{linesearch::MoreThuenteLineSearch, neldermead::NelderMead, particleswarm::ParticleSwarm, quasinewton::LBFGS, simulatedannealing::{SATempFunc, SimulatedAnnealing}}};
+use egui::{DragValue, Slider};
+
+use crate::{cmaes::CmaEs, regressor::{Cancellable, ProgressObserver, Regressor}};
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Optimizer {
+    NelderMead { max_iters: u64, tolerance: f64 },
+    /// global search, every particle costs one simulation per iteration
+    ParticleSwarm { max_iters: u64, particles: usize },
+    SimulatedAnnealing { max_iters: u64, temperature: f64, stall: u64 },
+    /// gradients by central finite differences
+    Lbfgs { max_iters: u64, tolerance: f64, memory: usize },
+    /// global search that learns the shape of the cost around its mean, one simulation per sample
+    CmaEs { max_iters: u64, population: usize, sigma: f64, tolerance: f64 },
+}
+
+/// What an optimizer run leaves behind, the parameters are still in the transformed space
+pub struct Run {
+    pub best_param: Option<Vec<f64>>,
+    pub best_cost: f64,
+    pub iterations: u64,
+    pub termination: String,
+}
+impl Run {
+    fn new<I: SolverState<Float = f64>>(state: &I, best_param: Option<Vec<f64>>) -> Self {
+        Self {
+            best_param,
+            best_cost: state.get_best_cost(),
+            iterations: state.get_iter(),
+            termination: state.get_termination_reason().map(|reason| reason.text().to_string()).unwrap_or_default(),
+        }
+    }
+}
+
+// the transformed space maps (-BOUND, BOUND) onto all but about 1e-5 of each target's interval
+const BOUND: f64 = 12.;
+
+impl Optimizer {
+    pub fn default() -> Self {
+        Optimizer::NelderMead { max_iters: 1000, tolerance: 1e-5 }
+    }
+
+    pub const DEFAULTS: [(&'static str, Optimizer); 5] = [
+        ("Nelder-Mead", Optimizer::NelderMead { max_iters: 1000, tolerance: 1e-5 }),
+        ("Particle swarm", Optimizer::ParticleSwarm { max_iters: 100, particles: 20 }),
+        ("Simulated annealing", Optimizer::SimulatedAnnealing { max_iters: 1000, temperature: 0.1, stall: 200 }),
+        ("L-BFGS", Optimizer::Lbfgs { max_iters: 200, tolerance: 1e-8, memory: 7 }),
+        ("CMA-ES", Optimizer::CmaEs { max_iters: 200, population: 12, sigma: 1., tolerance: 1e-6 }),
+    ];
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            Optimizer::NelderMead { .. } => "Nelder-Mead",
+            Optimizer::ParticleSwarm { .. } => "Particle swarm",
+            Optimizer::SimulatedAnnealing { .. } => "Simulated annealing",
+            Optimizer::Lbfgs { .. } => "L-BFGS",
+            Optimizer::CmaEs { .. } => "CMA-ES",
+        }
+    }
+
+    /// Minimizes `cost` from `start`, both in the transformed space of the regressor.
+    pub fn run<F>(&self, cost: Regressor, start: Vec<f64>, cancel: Arc<AtomicBool>, progress: F) -> Result<Run, Error>
+    where
+        F: FnMut(u64, f64) + 'static,
+    {
+        let observer = ProgressObserver(progress);
+        match *self {
+            Optimizer::NelderMead { max_iters, tolerance } => {
+                // simplex of n + 1 vertices around the start, one unit apart
+                let mut initial_points = vec![start.clone()];
+                for i in 0..start.len() {
+                    let mut vertex = start.clone();
+                    vertex[i] += 1.;
+                    initial_points.push(vertex);
+                }
+                let solver = NelderMead::new(initial_points).with_sd_tolerance(tolerance)?;
+                let res = Executor::new(cost, Cancellable::new(solver, cancel))
+                .configure(|state| state.max_iters(max_iters))
+                .add_observer(observer, ObserverMode::Always)
+                .run()?;
+                Ok(Run::new(&res.state, res.state.get_best_param().cloned()))
+            },
+            Optimizer::ParticleSwarm { max_iters, particles } => {
+                let bounds = (vec![-BOUND; start.len()], vec![BOUND; start.len()]);
+                // the swarm is costed through argmin's rayon `bulk_cost`, one particle per core
+                let solver = ParticleSwarm::new(bounds, particles);
+                let res = Executor::new(cost, Cancellable::new(solver, cancel))
+                .configure(|state| state.max_iters(max_iters))
+                .add_observer(observer, ObserverMode::Always)
+                .run()?;
+                Ok(Run::new(&res.state, res.state.get_best_param().map(|particle| particle.position.clone())))
+            },
+            Optimizer::SimulatedAnnealing { max_iters, temperature, stall } => {
+                // the temperature also sets the step size, the default T0/k cools too fast to move
+                let solver = SimulatedAnnealing::new(temperature)?
+                .with_temp_func(SATempFunc::Boltzmann)
+                .with_stall_best(stall);
+                let res = Executor::new(cost, Cancellable::new(solver, cancel))
+                .configure(|state| state.param(start).max_iters(max_iters))
+                .add_observer(observer, ObserverMode::Always)
+                .run()?;
+                Ok(Run::new(&res.state, res.state.get_best_param().cloned()))
+            },
+            Optimizer::Lbfgs { max_iters, tolerance, memory } => {
+                let solver = LBFGS::new(MoreThuenteLineSearch::new(), memory)
+                .with_tolerance_grad(tolerance)?;
+                let res = Executor::new(cost, Cancellable::new(solver, cancel))
+                .configure(|state| state.param(start).max_iters(max_iters))
+                .add_observer(observer, ObserverMode::Always)
+                .run()?;
+                Ok(Run::new(&res.state, res.state.get_best_param().cloned()))
+            },
+            Optimizer::CmaEs { max_iters, population, sigma, tolerance } => {
+                // each generation is costed through `bulk_cost` like the swarm
+                let solver = CmaEs::new(population, sigma, tolerance, BOUND);
+                let res = Executor::new(cost, Cancellable::new(solver, cancel))
+                .configure(|state| state.param(start).max_iters(max_iters))
+                .add_observer(observer, ObserverMode::Always)
+                .run()?;
+                Ok(Run::new(&res.state, res.state.get_best_param().cloned()))
+            },
+        }
+    }
+
+    pub fn view(&mut self, ui: &mut egui::Ui) -> bool {
+        let mut changed = false;
+        ui.horizontal_wrapped(|ui| {
+            for (label, default) in Optimizer::DEFAULTS {
+                let selected = std::mem::discriminant(self) == std::mem::discriminant(&default);
+                if ui.selectable_label(selected, label).clicked() && !selected {
+                    *self = default;
+                    changed = true;
+                }
+            }
+        });
+        match self {
+            Optimizer::NelderMead { max_iters, tolerance } => {
+                changed |= ui.add(DragValue::new(max_iters).clamp_range(1..=100_000).prefix("iterations: ")).changed();
+                changed |= ui.add(Slider::new(tolerance, 1e-12..=1e-1).logarithmic(true).text("simplex std tolerance")).changed();
+            },
+            Optimizer::ParticleSwarm { max_iters, particles } => {
+                changed |= ui.add(DragValue::new(max_iters).clamp_range(1..=100_000).prefix("iterations: ")).changed();
+                changed |= ui.add(DragValue::new(particles).clamp_range(2..=500).prefix("particles: ")).changed();
+            },
+            Optimizer::SimulatedAnnealing { max_iters, temperature, stall } => {
+                changed |= ui.add(DragValue::new(max_iters).clamp_range(1..=100_000).prefix("iterations: ")).changed();
+                changed |= ui.add(Slider::new(temperature, 1e-3..=10.).logarithmic(true).text("initial temperature")).changed();
+                changed |= ui.add(DragValue::new(stall).clamp_range(1..=100_000).prefix("stop after iterations without improvement: ")).changed();
+            },
+            Optimizer::Lbfgs { max_iters, tolerance, memory } => {
+                changed |= ui.add(DragValue::new(max_iters).clamp_range(1..=100_000).prefix("iterations: ")).changed();
+                changed |= ui.add(Slider::new(tolerance, 1e-14..=1e-2).logarithmic(true).text("gradient tolerance")).changed();
+                changed |= ui.add(DragValue::new(memory).clamp_range(1..=50).prefix("memory: ")).changed();
+            },
+            Optimizer::CmaEs { max_iters, population, sigma, tolerance } => {
+                changed |= ui.add(DragValue::new(max_iters).clamp_range(1..=100_000).prefix("generations: ")).changed();
+                changed |= ui.add(DragValue::new(population).clamp_range(4..=500).prefix("population: ")).changed();
+                changed |= ui.add(Slider::new(sigma, 1e-2..=BOUND).logarithmic(true).text("initial step size")).changed();
+                changed |= ui.add(Slider::new(tolerance, 1e-12..=1e-1).logarithmic(true).text("step size tolerance")).changed();
+            },
+        }
+        changed
+    }
+}
@@ -0,0 +1,147 @@
+use std::fmt::{self, Debug, Formatter};
+
+use crate::{model::Bioreactor, regressor::Group};
+
+/// A numeric field of the bioreactor that can be fitted, profiled or exported
+pub struct Parameter {
+    path: &'static str,
+    label: &'static str,
+    unit: &'static str,
+    lower: f64,
+    upper: f64,
+    /// measured group whose first point estimates the value
+    group: Option<Group>,
+    get: fn(&Bioreactor) -> f64,
+    set: fn(&mut Bioreactor, f64),
+}
+
+impl Parameter {
+    /// field path in the bioreactor, e.g. `airation.pid.minimum`
+    pub fn path(&self) -> &'static str {
+        self.path
+    }
+
+    pub fn label(&self) -> &'static str {
+        self.label
+    }
+
+    pub fn unit(&self) -> &'static str {
+        self.unit
+    }
+
+    /// default search interval, the range of the matching slider
+    pub fn bounds(&self) -> (f64, f64) {
+        (self.lower, self.upper)
+    }
+
+    /// whether `val` makes physical sense, wider than `bounds`: only fields whose range
+    /// goes below zero may be negative
+    pub fn admits(&self, val: f64) -> bool {
+        val.is_finite() && (val >= 0. || self.lower < 0.)
+    }
+
+    pub fn group(&self) -> Option<Group> {
+        self.group.clone()
+    }
+
+    pub fn get(&self, sim: &Bioreactor) -> f64 {
+        (self.get)(sim)
+    }
+
+    pub fn set(&self, sim: &mut Bioreactor, val: f64) {
+        (self.set)(sim, val)
+    }
+
+    pub fn find(path: &str) -> Option<&'static Parameter> {
+        PARAMETERS.iter().find(|param| param.path == path)
+    }
+}
+
+impl PartialEq for Parameter {
+    fn eq(&self, other: &Self) -> bool {
+        self.path == other.path
+    }
+}
+
+impl Debug for Parameter {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.path)
+    }
+}
+
+macro_rules! parameter {
+    ($($field:ident).+, $label:literal, $unit:literal, $lower:expr, $upper:expr $(, $group:expr)?) => {
+        Parameter {
+            path: stringify!($($field).+),
+            label: $label,
+            unit: $unit,
+            lower: $lower,
+            upper: $upper,
+            group: parameter!(@group $($group)?),
+            get: |sim| sim.$($field).+,
+            set: |sim, val| sim.$($field).+ = val,
+        }
+    };
+    (@group) => { None };
+    (@group $group:expr) => { Some($group) };
+}
+
+/// Every numeric field of `Bioreactor`. Adding one here makes it fittable everywhere.
+pub static PARAMETERS: &[Parameter] = &[
+    parameter!(mu_max, "mu max", "1/min", 0., 0.01),
+    parameter!(power_input, "Power input", "W/m3", 0., 100.),
+    parameter!(ks_glucose, "ks glucose", "g/L", 0., 0.2),
+    parameter!(ks_glutamine, "ks glutamine", "g/L", 0., 0.2),
+
+    parameter!(temp_shift.n_vcd, "n_vcd", "-", 0., 2.),
+    parameter!(temp_shift.day, "shift day", "day", 0., 14.),
+
+    parameter!(death.k_d, "kd", "1/min", 0., 1e-4),
+    parameter!(death.ks_death, "ks death", "g/L", 0., 2.),
+    parameter!(death.k_tox_lactate, "lactate toxicity", "g/L", 0.01, 20.),
+    parameter!(death.k_tox_ammonia, "ammonia toxicity", "g/L", 0.01, 10.),
+    parameter!(death.k_lysis, "k lysis", "1/min", 0., 1e-4),
+
+    parameter!(constants.product, "Product", "mg/(MVC min)", 0., 0.001),
+    parameter!(constants.k_glucose, "Glucose", "1/min", 0., 0.001),
+    parameter!(constants.k_glutamine, "Glutamin", "1/min", 0., 0.001),
+    parameter!(constants.kDO, "DO", "%", 0., 0.001),
+    parameter!(constants.y_lactate_glucose, "lactate yield", "g/g", 0., 2.),
+    parameter!(constants.k_lactate, "lactate uptake", "1/min", 0., 0.001),
+    parameter!(constants.ks_lactate, "ks lactate", "g/L", 0., 5.),
+    parameter!(constants.lactate_shift, "lactate shift", "g/L", 0., 10.),
+    parameter!(constants.y_ammonia_glutamine, "ammonia yield", "g/g", 0., 1.),
+    parameter!(constants.ki_lactate, "lactate inhibition", "g/L", 0.01, 50.),
+    parameter!(constants.ki_ammonia, "ammonia inhibition", "g/L", 0.01, 10.),
+
+    parameter!(airation.cell_metabolism, "cell metabolism", "mol/(cell min)", 0., 100.),
+    parameter!(airation.air_flow, "air flow", "VVh", 0., 10.),
+    parameter!(airation.henry, "Henry", "mol/(bar L)", 0., 10.),
+    parameter!(airation.pid.minimum, "DO setpoint", "%", 0., 100.),
+    parameter!(airation.pid.kp, "kP", "1/%", 0., 1.),
+    parameter!(airation.pid.ki, "kI", "1/(% min)", 0., 0.001),
+    parameter!(airation.pid.kd, "kD", "min/%", 0., 1.),
+    parameter!(airation.pid.derivative_filter, "derivative filter", "min", 0.1, 60.),
+    parameter!(airation.pid.actuator_lag, "actuator lag", "min", 0.1, 60.),
+    parameter!(airation.pid.fi_oxygen_max, "fi oxygen max", "L/min", 0., 100.),
+    parameter!(airation.pid.max_flow, "max O2 flow", "L/min", 0.1, 100.),
+
+    parameter!(initial.volume, "Volume₀", "L", 0., 100.),
+    parameter!(initial.vcd, "VCD₀", "MVC/mL", 0., 10., Group::VCD),
+    parameter!(initial.glucose, "Glucose₀", "g/L", 0., 20., Group::Glucose),
+    parameter!(initial.glutamine, "Glutamine₀", "g/L", 0., 20., Group::Glutamin),
+    parameter!(initial.oxygen_part, "DO₀", "%", 0., 100., Group::DO),
+    parameter!(initial.lactate, "Lactate₀", "g/L", 0., 10., Group::Lactate),
+    parameter!(initial.ammonia, "Ammonia₀", "g/L", 0., 5., Group::Ammonia),
+    parameter!(initial.viability, "Viability₀", "%", 1., 100., Group::Viability),
+
+    parameter!(feeding.start, "feed start", "day", 0., 14.),
+    parameter!(feeding.rate, "Feed rate", "IWV/day", 0., 1.),
+    parameter!(feeding.glucose, "feed glucose", "g/L", 0., 100.),
+    parameter!(feeding.glutamine, "feed glutamine", "g/L", 0., 100.),
+];
+
+/// registry entry of a path known to exist, for hard-coded defaults
+pub fn by_path(path: &str) -> &'static Parameter {
+    Parameter::find(path).unwrap_or_else(|| panic!("no parameter at {}", path))
+}
@@ -0,0 +1,110 @@
+use std::sync::{atomic::{AtomicBool, Ordering}, Arc};
+
+use crate::{parameter::Parameter, regressor::{fit_point, Regressor}};
+
+/// 95% quantile of chi² with one degree of freedom
+const CHI2_1_95: f64 = 3.841458820694124;
+
+/// Range a profiled parameter is stepped over
+#[derive(Debug, Clone)]
+pub struct ProfileSettings {
+    pub target: &'static Parameter,
+    pub lower: f64,
+    pub upper: f64,
+    pub steps: usize,
+    pub logarithmic: bool,
+}
+impl ProfileSettings {
+    pub fn new(target: &'static Parameter, value: f64) -> Self {
+        let (lower, upper) = if value > 0. { (value / 2., value * 2.) } else { target.bounds() };
+        Self { target, lower, upper, steps: 15, logarithmic: true }
+    }
+
+    pub fn values(&self) -> Vec<f64> {
+        let n = self.steps.max(2);
+        (0..n).map(|i| {
+            let t = i as f64 / (n - 1) as f64;
+            if self.logarithmic && self.lower > 0. {
+                self.lower * (self.upper / self.lower).powf(t)
+            } else {
+                self.lower + (self.upper - self.lower) * t
+            }
+        }).collect()
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Profile {
+    pub target: &'static Parameter,
+    /// profiled value against the sum of squared weighted residuals with the other targets re-fitted
+    pub points: Vec<[f64; 2]>,
+    /// values within the 95% likelihood ratio bound
+    pub threshold: f64,
+}
+impl Profile {
+    /// range of profiled values under the threshold, `None` when no point is
+    pub fn interval(&self) -> Option<(f64, f64)> {
+        let inside = self.points.iter().filter(|[_, ssr]| *ssr <= self.threshold).map(|[x, _]| *x);
+        inside.fold(None, |acc, x| match acc {
+            None => Some((x, x)),
+            Some((low, high)) => Some((low.min(x), high.max(x))),
+        })
+    }
+
+    /// the interval touching an end of the stepped range means the data do not bound the parameter there
+    pub fn identifiable(&self) -> bool {
+        match (self.interval(), self.points.first(), self.points.last()) {
+            (Some((low, high)), Some(first), Some(last)) => low > first[0] && high < last[0],
+            _ => false,
+        }
+    }
+}
+
+/// Steps `settings.target` over its range and re-fits the other targets of `regressor` at every step,
+/// warm started from the previous step. `progress` gets the step and its SSR.
+pub fn profile<F>(regressor: Regressor, settings: ProfileSettings, cancel: Arc<AtomicBool>, mut progress: F) -> Result<Profile, String>
+where
+    F: FnMut(u64, f64),
+{
+    let mut inner = regressor;
+    inner.param.targets.retain(|fit| fit.target != settings.target);
+
+    let mut points = Vec::new();
+    let mut residual_count = 0;
+    for (i, value) in settings.values().into_iter().enumerate() {
+        if cancel.load(Ordering::Relaxed) {
+            return Err("Cancelled".to_string());
+        }
+        inner.set_all(settings.target, value);
+
+        let best = if inner.slots().is_empty() {
+            Vec::new()
+        } else {
+            let run = fit_point(&inner, cancel.clone(), |_, _| {}).map_err(|er| er.to_string())?;
+            run.best_param.ok_or("re-fit found no parameters")?
+        };
+        let residuals = inner.residual_vector(&best).map_err(|er| er.to_string())?;
+        residual_count = residuals.len();
+        let ssr = residuals.iter().map(|r| r.powi(2)).sum::<f64>();
+
+        // the next step starts here, not at the data
+        inner.param.targets.iter_mut().for_each(|fit| fit.from_data = false);
+        for (slot, val) in inner.slots().into_iter().zip(&best) {
+            match slot.batch {
+                Some(batch) => {
+                    let target = inner.param.targets[slot.fit].target;
+                    target.set(&mut inner.batches[batch].simulation, *val);
+                },
+                None => inner.param.targets[slot.fit].initial = Some(*val),
+            }
+        }
+        points.push([value, ssr]);
+        progress(i as u64, ssr);
+    }
+
+    // unknown noise level: m ln(SSR / SSR_min) is chi² distributed
+    let best = points.iter().map(|[_, ssr]| *ssr).fold(f64::INFINITY, f64::min);
+    let threshold = best * (CHI2_1_95 / residual_count.max(1) as f64).exp();
+
+    Ok(Profile { target: settings.target, points, threshold })
+}
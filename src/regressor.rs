@@ -1,16 +1,43 @@
-use std::fmt::{self, Display, Formatter};
+use std::{fmt::{self, Display, Formatter}, sync::{atomic::{AtomicBool, Ordering}, Arc}};
 
-use argmin::core::{CostFunction, Error};
-use crate::{model::{Bioreactor, State}, ui::tree::{self}};
+use argmin::{core::{observers::Observe, CostFunction, Error, Gradient, Problem, Solver, State as SolverState, TerminationReason, TerminationStatus, KV}, solver::simulatedannealing::Anneal};
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use crate::{ensemble::simulate_all, model::Bioreactor, optimizer::{Optimizer, Run}, parameter::{self, Parameter}, simulation::{SimSettings, SimulationResult}, solver::SolverError, statistics::{self, Confidence}, ui::tree::{self}};
 use crate::ui::tree::{Tree, ParentNode};
 
-#[derive(Debug, PartialEq, Eq, Clone)]
+#[derive(Debug, PartialEq, Eq, Clone, Serialize, Deserialize)]
 pub enum Group {
     VCD,
     Glucose,
     Glutamin,
     DO,
     Product,
+    Lactate,
+    Ammonia,
+    TCD,
+    Viability,
+}
+
+impl Group {
+    pub const ALL: [Group; 9] = [
+        Group::VCD,
+        Group::Glucose,
+        Group::Glutamin,
+        Group::Product,
+        Group::DO,
+        Group::Lactate,
+        Group::Ammonia,
+        Group::TCD,
+        Group::Viability,
+    ];
+}
+
+impl Group {
+    /// position in `Group::ALL`
+    pub fn index(&self) -> usize {
+        Group::ALL.iter().position(|group| group == self).unwrap()
+    }
 }
 
 impl Display for Group {
@@ -19,18 +46,123 @@ impl Display for Group {
     }
 }
 
+/// A fitted target with the interval it is kept in
+#[derive(Clone, Debug)]
+pub struct FitTarget {
+    pub target: &'static Parameter,
+    pub lower: f64,
+    pub upper: f64,
+    /// starting value, the current bioreactor value when `None`
+    pub initial: Option<f64>,
+    /// every batch gets its own value instead of sharing one
+    pub per_batch: bool,
+    /// start an initial condition at the first measurement of its group
+    pub from_data: bool,
+}
+impl FitTarget {
+    pub fn new(target: &'static Parameter) -> Self {
+        let (lower, upper) = target.bounds();
+        Self { target, lower, upper, initial: None, per_batch: false, from_data: false }
+    }
+
+    /// starting value, pulled inside the bounds
+    pub fn start(&self, sim: &Bioreactor) -> f64 {
+        self.initial.unwrap_or(self.target.get(sim)).clamp(self.lower, self.upper)
+    }
+
+    /// bounds that meet leave nothing to fit, the target is held at that value
+    pub fn fixed(&self) -> bool {
+        self.lower == self.upper
+    }
+
+    // the optimizer works on an unbounded value that a logistic maps into (lower, upper)
+    fn to_physical(&self, z: f64) -> f64 {
+        self.lower + (self.upper - self.lower) / (1. + (-z).exp())
+    }
+
+    fn to_internal(&self, x: f64) -> f64 {
+        let fraction = ((x - self.lower) / (self.upper - self.lower)).clamp(1e-9, 1. - 1e-9);
+        (fraction / (1. - fraction)).ln()
+    }
+}
+
 #[derive(Clone, Debug)]
 pub struct Param {
-    pub target: Target,
-    pub mode: Mode
+    pub targets: Vec<FitTarget>,
+    pub mode: Mode,
+    pub metric: Metric,
+    pub weighting: Weighting,
+    pub optimizer: Optimizer,
 }
 impl Param {
     pub fn default() -> Self {
         Self {
-            target: Target::MuMax,
+            targets: vec![FitTarget::new(parameter::by_path("mu_max"))],
             mode: Mode::Mixed,
+            metric: Metric::Sse,
+            weighting: Weighting::default(),
+            optimizer: Optimizer::default(),
+        }
+    }
+
+    pub fn contains(&self, target: &Parameter) -> bool {
+        self.targets.iter().any(|t| t.target == target)
+    }
+
+    pub fn toggle(&mut self, target: &'static Parameter) {
+        if let Some(pos) = self.targets.iter().position(|t| t.target == target) {
+            self.targets.remove(pos);
+        } else {
+            self.targets.push(FitTarget::new(target));
         }
     }
+
+    pub fn bounds_view(&mut self, ui: &mut egui::Ui, sim: &Bioreactor) -> bool {
+        let mut changed = false;
+        egui::Grid::new("target bounds").num_columns(5).show(ui, |ui| {
+            ui.label("");
+            ui.label("lower");
+            ui.label("upper");
+            ui.label("initial");
+            ui.label("");
+            ui.end_row();
+            for fit in self.targets.iter_mut() {
+                let speed = (fit.upper - fit.lower).abs().max(1e-9) * 1e-3;
+                ui.label(format!("{} [{}]", fit.target.label(), fit.target.unit())).on_hover_text(fit.target.path());
+                // the logistic map needs finite bounds with room between them, edits that break that are undone
+                let (lower, upper) = (fit.lower, fit.upper);
+                changed |= ui.add(egui::DragValue::new(&mut fit.lower).speed(speed).max_decimals(10).clamp_range(f64::MIN..=fit.upper)).changed();
+                changed |= ui.add(egui::DragValue::new(&mut fit.upper).speed(speed).max_decimals(10).clamp_range(fit.lower..=f64::MAX)).changed();
+                if !(fit.lower.is_finite() && fit.upper.is_finite() && fit.lower < fit.upper) {
+                    fit.lower = lower;
+                    fit.upper = upper;
+                }
+                ui.horizontal(|ui| {
+                    if fit.target.group().is_some() {
+                        if ui.checkbox(&mut fit.from_data, "first point").on_hover_text("start at the first measurement of every batch").changed() {
+                            fit.initial = None;
+                            changed = true;
+                        }
+                    }
+                    if fit.from_data {
+                        return;
+                    }
+                    let mut from_model = fit.initial.is_none();
+                    if ui.checkbox(&mut from_model, "current").changed() {
+                        fit.initial = if from_model { None } else { Some(fit.start(sim)) };
+                        changed = true;
+                    }
+                    match &mut fit.initial {
+                        Some(initial) => changed |= ui.add(egui::DragValue::new(initial).speed(speed).max_decimals(10).clamp_range(fit.lower..=fit.upper)).changed(),
+                        None => { ui.label(format!("{:.4e}", fit.start(sim))); },
+                    }
+                });
+                changed |= ui.checkbox(&mut fit.per_batch, "per batch").on_hover_text("fit a separate value for every batch").changed();
+                ui.end_row();
+            }
+        });
+        changed
+    }
 }
 
 #[derive(Clone, Debug, PartialEq)]
@@ -39,16 +171,139 @@ pub enum Mode {
     Mixed
 }
 
+/// How the residuals of all matched measurements are reduced to a cost
 #[derive(Clone, Debug, PartialEq)]
-pub enum Target {
-    MuMax,
-    NVcd,
-    FeedRate,
-    Glucose,
-    Glutamin,
-    Product,
-    DO,
+pub enum Metric {
+    Sse,
+    /// residuals relative to the measured value
+    WeightedSse,
+    /// RMSE of every group divided by the range of its measurements, summed over groups
+    Nrmse,
 }
+impl Metric {
+    pub const ALL: [Metric; 3] = [Metric::Sse, Metric::WeightedSse, Metric::Nrmse];
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            Metric::Sse => "SSE",
+            Metric::WeightedSse => "Weighted SSE",
+            Metric::Nrmse => "NRMSE",
+        }
+    }
+}
+
+/// Typical magnitude the SSE of a group is divided by, so groups in different units can be summed
+#[derive(Clone, Debug, PartialEq)]
+pub enum Scaling {
+    None,
+    Range,
+    Std,
+    Mean,
+}
+impl Scaling {
+    pub const ALL: [Scaling; 4] = [Scaling::None, Scaling::Range, Scaling::Std, Scaling::Mean];
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            Scaling::None => "None",
+            Scaling::Range => "Range",
+            Scaling::Std => "Std",
+            Scaling::Mean => "Mean",
+        }
+    }
+
+    fn scale(&self, values: &[f64]) -> f64 {
+        let n = values.len() as f64;
+        let mean = values.iter().sum::<f64>() / n;
+        let scale = match self {
+            Scaling::None => 1.,
+            Scaling::Range => {
+                let (low, high) = values.iter().fold((f64::INFINITY, f64::NEG_INFINITY), |(low, high), y| (low.min(*y), high.max(*y)));
+                high - low
+            },
+            Scaling::Std => (values.iter().map(|y| (y - mean).powi(2)).sum::<f64>() / (n - 1.).max(1.)).sqrt(),
+            Scaling::Mean => mean.abs(),
+        };
+        // a single or constant measurement leaves nothing to scale by
+        if scale > 0. && scale.is_finite() { scale } else { 1. }
+    }
+}
+
+/// Per group weights for mixed mode fits
+#[derive(Clone, Debug)]
+pub struct Weighting {
+    pub scaling: Scaling,
+    pub weights: [f64; Group::ALL.len()], // in the order of Group::ALL
+}
+impl Weighting {
+    pub fn default() -> Self {
+        Self {
+            scaling: Scaling::Range,
+            weights: [1.; Group::ALL.len()],
+        }
+    }
+
+    pub fn weight(&self, group: &Group) -> f64 {
+        self.weights[group.index()]
+    }
+
+    pub fn view(&mut self, ui: &mut egui::Ui) -> bool {
+        let mut changed = false;
+        ui.horizontal(|ui| {
+            ui.label("SSE scaling");
+            for scaling in Scaling::ALL {
+                let label = scaling.label();
+                changed |= ui.selectable_value(&mut self.scaling, scaling, label).changed();
+            }
+        });
+        egui::Grid::new("group weights").num_columns(2).show(ui, |ui| {
+            for group in Group::ALL {
+                ui.label(group.to_string());
+                changed |= ui.add(egui::DragValue::new(&mut self.weights[group.index()]).speed(0.05).clamp_range(0.0..=100.)).changed();
+                ui.end_row();
+            }
+        });
+        changed
+    }
+}
+
+/// Residual metrics of one measurement group
+#[derive(Clone, Debug)]
+pub struct GroupResiduals {
+    pub batch: usize,
+    pub group: Group,
+    pub n: usize,
+    pub sse: f64,
+    pub weighted_sse: f64,
+    pub nrmse: f64,
+    pub weight: f64,
+    pub scale: f64,
+    residuals: Vec<f64>,
+    relative: Vec<f64>,
+    range: f64,
+}
+impl GroupResiduals {
+    /// residuals whose sum of squares is the least squares form of `metric` for this group
+    pub fn weighted(&self, metric: &Metric) -> Vec<f64> {
+        let w = self.weight.sqrt();
+        match metric {
+            Metric::Sse => self.residuals.iter().map(|r| w * r / self.scale).collect(),
+            Metric::WeightedSse => self.relative.iter().map(|r| w * r).collect(),
+            Metric::Nrmse => self.residuals.iter().map(|r| w * r / (self.range * (self.n as f64).sqrt())).collect(),
+        }
+    }
+
+    /// contribution of the group to the total cost
+    pub fn cost(&self, metric: &Metric) -> f64 {
+        let value = match metric {
+            Metric::Sse => self.sse / self.scale.powi(2),
+            Metric::WeightedSse => self.weighted_sse,
+            Metric::Nrmse => self.nrmse,
+        };
+        self.weight * value
+    }
+}
+
 #[derive(Clone, Debug)]
 pub struct RegressorNode {
     pub group: Group,
@@ -63,26 +318,11 @@ impl RegressorNode {
     pub fn translate(tree: Tree) -> Vec<RegressorNode> {
         let mut out = Vec::new();
         for ParentNode { name, children } in tree.nodes {
-
-            match name.as_ref() {
-                "VCD" => {
-                    for tree::Node { x, y } in children {
-                        out.push(RegressorNode::new(Group::VCD, x, y));
-                    }
-                },
-                "Glucose" => {
-                    for tree::Node { x, y } in children {
-                        out.push(RegressorNode::new(Group::Glucose, x, y));
-                    }
-                },
-                "Glutamin" => {
-                    for tree::Node { x, y } in children {
-                        out.push(RegressorNode::new(Group::Glutamin, x, y));
-                    }
-                },
-                _ => {}
+            if let Some(group) = Group::ALL.iter().find(|group| group.to_string() == name) {
+                for tree::Node { x, y } in children {
+                    out.push(RegressorNode::new(group.clone(), x, y));
+                }
             }
-
         }
 
         out.sort_by(|a,b| {
@@ -93,109 +333,452 @@ impl RegressorNode {
     }
 }
 
-pub struct Regressor {
+/// One run with its measurements and the bioreactor it was made in
+#[derive(Clone)]
+pub struct RegressorBatch {
+    pub name: String,
     pub nodes: Vec<RegressorNode>,
     pub simulation: Bioreactor,
-    pub param: Param,
-    pub epsilon: f64,
+}
+impl RegressorBatch {
+    /// earliest measurement of `group`
+    pub fn first(&self, group: &Group) -> Option<f64> {
+        self.nodes.iter().filter(|node| node.group == *group && node.x.is_finite())
+            .min_by(|a, b| a.x.total_cmp(&b.x))
+            .map(|node| node.y)
+    }
+}
+
+/// Position of one optimizer value: a fitted target, for every batch or for one of them
+#[derive(Clone, Debug, PartialEq)]
+pub struct Slot {
+    pub fit: usize, // index into Param::targets
+    pub batch: Option<usize>,
+}
 
+#[derive(Clone)]
+pub struct Regressor {
+    pub batches: Vec<RegressorBatch>,
+    pub param: Param,
+    pub settings: SimSettings,
 }
 
 impl Regressor {
     fn default() -> Self {
+        Self::single(Vec::new(), Bioreactor::default(), Param::default(), SimSettings::default())
+    }
+
+    pub fn single(nodes: Vec<RegressorNode>, simulation: Bioreactor, param: Param, settings: SimSettings) -> Self {
         Self {
-            nodes: Vec::new(),
-            simulation: Bioreactor::default(),
-            epsilon: 1e-3,
-            param: Param::default(),
+            batches: vec![RegressorBatch { name: String::new(), nodes, simulation }],
+            param,
+            settings,
         }
     }
-}
 
-impl CostFunction for Regressor {
-    type Param = f64;
-    type Output = f64;
+    /// shared targets take one value, per batch targets one per batch, fixed targets none
+    pub fn slots(&self) -> Vec<Slot> {
+        let mut out = Vec::new();
+        for (fit, target) in self.param.targets.iter().enumerate() {
+            if target.fixed() {
+                continue;
+            }
+            if target.per_batch && self.batches.len() > 1 {
+                out.extend((0..self.batches.len()).map(|batch| Slot { fit, batch: Some(batch) }));
+            } else {
+                out.push(Slot { fit, batch: None });
+            }
+        }
+        out
+    }
 
-    fn cost(&self, val: &Self::Param) -> Result<Self::Output, Error> {
+    pub fn slot_target(&self, slot: &Slot) -> &FitTarget {
+        &self.param.targets[slot.fit]
+    }
 
-        if *val < 0. {
-            return Ok(100_000.)
+    pub fn slot_label(&self, slot: &Slot) -> String {
+        let label = self.slot_target(slot).target.label();
+        match slot.batch {
+            Some(batch) => format!("{} [{}]", label, self.batches[batch].name),
+            None => label.to_string(),
         }
+    }
 
-        const MINUTES: f64 = 14. * 24. * 60.;
+    /// sets `target` in the bioreactor of every batch
+    pub fn set_all(&mut self, target: &Parameter, val: f64) {
+        for batch in self.batches.iter_mut() {
+            target.set(&mut batch.simulation, val);
+        }
+    }
 
-        const STEP: f64 = 2.; // step increment lower is more precise but more computationaly intense
-        
-        let initial_state = State::from([
-            self.simulation.initial.volume, 
-            self.simulation.initial.vcd, 
-            self.simulation.initial.glucose, 
-            self.simulation.initial.glutamine, 
-            (self.simulation.initial.oxygen_part * self.simulation.oxigen_saturation()) / 100., 
-            0., 
-            0. 
-        ]);
+    /// the bioreactor of every batch with the fitted targets set to `vals`
+    pub fn apply(&self, vals: &[f64]) -> Vec<Bioreactor> {
+        let mut out: Vec<Bioreactor> = self.batches.iter().map(|batch| batch.simulation.clone()).collect();
+        for fit in self.param.targets.iter().filter(|fit| fit.fixed()) {
+            out.iter_mut().for_each(|sim| fit.target.set(sim, fit.lower));
+        }
+        for (slot, val) in self.slots().iter().zip(vals) {
+            let target = &self.slot_target(slot).target;
+            match slot.batch {
+                Some(batch) => target.set(&mut out[batch], *val),
+                None => out.iter_mut().for_each(|sim| target.set(sim, *val)),
+            }
+        }
+        out
+    }
+
+    /// every measurement of the fitted groups in `batch` with the simulation interpolated at its time
+    pub fn pairs(&self, batch: usize, sim: &SimulationResult) -> Vec<(&RegressorNode, f64)> {
+        self.batches[batch].nodes.iter().filter(|node| match &self.param.mode {
+            Mode::Single(group) => node.group == *group,
+            Mode::Mixed => true,
+        }).filter_map(|node| sim.sample(sim.group(&node.group), node.x).map(|y| (node, y))).collect()
+    }
+
+    /// residuals of all batches with the targets set to `vals`
+    pub fn evaluate(&self, vals: &[f64]) -> Result<Vec<GroupResiduals>, SolverError> {
+        let mut out = Vec::new();
+        for (batch, sim) in simulate_all(&self.apply(vals), &self.settings).into_iter().enumerate() {
+            out.extend(self.residuals(batch, &sim?));
+        }
+        Ok(out)
+    }
 
-        let mut simulation = self.simulation.clone();
-        simulation.update(&self.param, *val);
+    /// all weighted residuals with the targets set to `vals`
+    pub fn residual_vector(&self, vals: &[f64]) -> Result<Vec<f64>, SolverError> {
+        Ok(self.evaluate(vals)?.iter().flat_map(|res| res.weighted(&self.param.metric)).collect())
+    }
 
-        let mut stepper = ode_solvers::Rk4::new(simulation, 0., initial_state, MINUTES, STEP);
-        let res = stepper.mut_integrate();
+    /// starting values of all slots, shared slots seeded from data look at the first batch
+    pub fn start(&self) -> Vec<f64> {
+        self.slots().iter().map(|slot| {
+            let fit = self.slot_target(slot);
+            let batch = &self.batches[slot.batch.unwrap_or(0)];
+            let seed = fit.target.group().filter(|_| fit.from_data).and_then(|group| batch.first(&group));
+            match seed {
+                Some(y) => y.clamp(fit.lower, fit.upper),
+                None => fit.start(&batch.simulation),
+            }
+        }).collect()
+    }
 
-        if let Ok(_val) = res {
+    pub fn to_physical(&self, z: &[f64]) -> Vec<f64> {
+        self.slots().iter().zip(z).map(|(slot, z)| self.slot_target(slot).to_physical(*z)).collect()
+    }
 
-            let nodes = match &self.param.mode {
-                Mode::Single(val) => {
-                    self.nodes.clone().into_iter()
-                    .filter(|node| {
-                        node.group == *val
-                    }).collect::<Vec<RegressorNode>>()
+    pub fn to_internal(&self, x: &[f64]) -> Vec<f64> {
+        self.slots().iter().zip(x).map(|(slot, x)| self.slot_target(slot).to_internal(*x)).collect()
+    }
+
+    /// Compares every measurement with the simulation interpolated at its exact time.
+    /// Measurements outside the simulated horizon are left out.
+    pub fn residuals(&self, batch: usize, sim: &SimulationResult) -> Vec<GroupResiduals> {
+        let mut out = Vec::new();
+        for group in Group::ALL {
+            if let Mode::Single(selected) = &self.param.mode {
+                if *selected != group {
+                    continue;
                 }
-                Mode::Mixed => self.nodes.clone(),
-            };
-
-            //let mut current_node = nodes.pop();
-            let mut result = 0.;
-            // for (t, y) in stepper.x_out().iter().zip(stepper.y_out()) {
-
-            //     match &current_node {
-            //         None => {println!("no more nodes"); break;},
-            //         Some(node) => {
-            //             println!("in some");
-            //             if (node.x - t).abs() > self.epsilon {
-            //                 continue;
-            //             }
-            //             let y = match node.group {
-            //                 Group::VCD => y[1],
-            //                 Group::Glucose => y[2],
-            //                 Group::Glutamin => y[3],
-            //             };
-
-            //             result += (y.powf(2.) - node.y.powf(2.)).abs();
-            //             current_node = nodes.pop();
-            //         }
-            //     }
-            // }
+            }
+            let nodes: Vec<&RegressorNode> = self.batches[batch].nodes.iter().filter(|node| node.group == group).collect();
+            if nodes.is_empty() {
+                continue;
+            }
+
+            let measured: Vec<f64> = nodes.iter().map(|node| node.y).collect();
+            let largest = nodes.iter().fold(0f64, |acc, node| acc.max(node.y.abs()));
+            let (low, high) = nodes.iter().fold((f64::INFINITY, f64::NEG_INFINITY), |(low, high), node| (low.min(node.y), high.max(node.y)));
 
+            let values = sim.group(&group);
+            let mut residuals = Vec::new();
+            let mut relative = Vec::new();
             for node in nodes {
-                for (t, y) in stepper.x_out().iter().zip(stepper.y_out()) {
-                    if (node.x - t).abs() > self.epsilon {
-                        continue;
-                    }
-                    let y = match node.group {
-                        Group::VCD => y[1],
-                        Group::Glucose => y[2],
-                        Group::Glutamin => y[3],
-                        Group::DO => y[4],
-                        Group::Product => y[6],
-                    };
-                    result += (y.powf(2.) - node.y.powf(2.)).abs();
+                if let Some(y) = sim.sample(values, node.x) {
+                    let residual = y - node.y;
+                    // floor keeps zero readings (e.g. product at inoculation) from dominating
+                    let scale = node.y.abs().max(1e-3 * largest).max(f64::MIN_POSITIVE);
+                    residuals.push(residual);
+                    relative.push(residual / scale);
                 }
             }
-            Ok(result)
+            let n = residuals.len();
+            if n == 0 {
+                continue;
+            }
+            let sse = residuals.iter().map(|r| r.powi(2)).sum::<f64>();
+            let weighted_sse = relative.iter().map(|r| r.powi(2)).sum::<f64>();
 
-        } else {
-            return Err(Error::msg("no result"))
+            let range = if high > low { high - low } else if largest > 0. { largest } else { 1. };
+            out.push(GroupResiduals {
+                batch,
+                weight: self.param.weighting.weight(&group),
+                scale: self.param.weighting.scaling.scale(&measured),
+                group,
+                n,
+                sse,
+                weighted_sse,
+                nrmse: (sse / n as f64).sqrt() / range,
+                residuals,
+                relative,
+                range,
+            });
+        }
+        out
+    }
+}
+
+impl CostFunction for Regressor {
+    type Param = Vec<f64>;
+    type Output = f64;
+
+    /// `vals` are the unbounded optimizer values, see `FitTarget`
+    fn cost(&self, vals: &Self::Param) -> Result<Self::Output, Error> {
+        let residuals = self.evaluate(&self.to_physical(vals)).map_err(|er| Error::msg(er.to_string()))?;
+        let result: f64 = residuals.iter().map(|res| res.cost(&self.param.metric)).sum();
+        // a diverged simulation has to rank below every real one
+        if !result.is_finite() {
+            return Ok(f64::INFINITY)
         }
+        Ok(result)
+    }
+}
+
+impl Gradient for Regressor {
+    type Param = Vec<f64>;
+    type Gradient = Vec<f64>;
+
+    /// central differences in the transformed space
+    fn gradient(&self, vals: &Self::Param) -> Result<Self::Gradient, Error> {
+        const H: f64 = 1e-4;
+        (0..vals.len()).map(|i| {
+            let (mut forward, mut backward) = (vals.clone(), vals.clone());
+            forward[i] += H;
+            backward[i] -= H;
+            Ok((self.cost(&forward)? - self.cost(&backward)?) / (2. * H))
+        }).collect()
     }
-}
\ No newline at end of file
+}
+
+impl Anneal for Regressor {
+    type Param = Vec<f64>;
+    type Output = Vec<f64>;
+    type Float = f64;
+
+    /// moves every target by up to `extent` in the transformed space
+    fn anneal(&self, vals: &Self::Param, extent: f64) -> Result<Self::Output, Error> {
+        let mut rng = rand::thread_rng();
+        Ok(vals.iter().map(|val| val + rng.gen_range(-1.0..=1.0) * extent).collect())
+    }
+}
+
+/// Outcome of a minimization, reduced to what the UI needs
+#[derive(Debug, Clone)]
+pub struct Fit {
+    pub optimizer: &'static str,
+    /// fitted target of every value in `best_param` and the batch it belongs to, `None` when shared
+    pub targets: Vec<(&'static Parameter, Option<usize>)>,
+    pub labels: Vec<String>,
+    pub batch_names: Vec<String>,
+    pub best_param: Option<Vec<f64>>,
+    pub best_cost: f64,
+    pub iterations: u64,
+    pub termination: String,
+    /// stopped by the user, `best_param` is only the best point seen so far
+    pub cancelled: bool,
+    pub metric: Metric,
+    /// residuals of the best parameters, per group
+    pub residuals: Vec<GroupResiduals>,
+    pub confidence: Result<Confidence, String>,
+}
+
+/// Termination text of a fit stopped through `Cancellable`
+pub const CANCELLED: &str = "Cancelled";
+
+/// Wraps a solver so that a running fit can be stopped between iterations
+pub struct Cancellable<S> {
+    solver: S,
+    cancel: Arc<AtomicBool>,
+}
+impl<S> Cancellable<S> {
+    pub fn new(solver: S, cancel: Arc<AtomicBool>) -> Self {
+        Self { solver, cancel }
+    }
+}
+impl<O, I: SolverState, S: Solver<O, I>> Solver<O, I> for Cancellable<S> {
+    const NAME: &'static str = S::NAME;
+
+    fn init(&mut self, problem: &mut Problem<O>, state: I) -> Result<(I, Option<KV>), Error> {
+        self.solver.init(problem, state)
+    }
+
+    fn next_iter(&mut self, problem: &mut Problem<O>, state: I) -> Result<(I, Option<KV>), Error> {
+        self.solver.next_iter(problem, state)
+    }
+
+    fn terminate(&mut self, state: &I) -> TerminationStatus {
+        if self.cancel.load(Ordering::Relaxed) {
+            return TerminationStatus::Terminated(TerminationReason::SolverExit(CANCELLED.to_string()));
+        }
+        self.solver.terminate(state)
+    }
+}
+
+/// Reports iteration and best cost after every iteration
+pub struct ProgressObserver<F>(pub F);
+impl<I: SolverState<Float = f64>, F: FnMut(u64, f64)> Observe<I> for ProgressObserver<F> {
+    fn observe_iter(&mut self, state: &I, _kv: &KV) -> Result<(), Error> {
+        (self.0)(state.get_iter(), state.get_best_cost());
+        Ok(())
+    }
+}
+
+/// Runs the optimizer alone from the initial values of the targets,
+/// unlike `Run` from `Optimizer::run` the best parameters are in physical units
+pub fn fit_point<F>(regressor: &Regressor, cancel: Arc<AtomicBool>, progress: F) -> Result<Run, Error>
+where
+    F: FnMut(u64, f64) + 'static,
+{
+    if regressor.slots().is_empty() {
+        return Err(Error::msg("every target has equal bounds, there is nothing to fit"));
+    }
+    let start = regressor.to_internal(&regressor.start());
+    let mut run = regressor.param.optimizer.run(regressor.clone(), start, cancel, progress)?;
+    run.best_param = run.best_param.map(|p| regressor.to_physical(&p));
+    Ok(run)
+}
+
+/// Fits the targets starting from their initial values and reports on the result, results are in physical units
+pub fn minimize<F>(regressor: Regressor, cancel: Arc<AtomicBool>, progress: F) -> Result<Fit, Error>
+where
+    F: FnMut(u64, f64) + 'static,
+{
+    let run = fit_point(&regressor, cancel, progress)?;
+    let slots = regressor.slots();
+    let targets = slots.iter().map(|slot| (regressor.slot_target(slot).target, slot.batch)).collect();
+    let labels = slots.iter().map(|slot| regressor.slot_label(slot)).collect();
+    let optimizer = regressor.param.optimizer.clone();
+
+    let best_param = run.best_param;
+    let residuals = match &best_param {
+        Some(p) => regressor.evaluate(p).unwrap_or_default(),
+        None => Vec::new(),
+    };
+
+    // a cancelled fit is not at a minimum, its Jacobian would not mean anything
+    let cancelled = run.termination == CANCELLED;
+    let confidence = match &best_param {
+        _ if cancelled => Err("the fit was cancelled".to_string()),
+        Some(p) => statistics::confidence(&regressor, p),
+        None => Err("no parameters were found".to_string()),
+    };
+
+    Ok(Fit {
+        optimizer: optimizer.label(),
+        targets,
+        labels,
+        batch_names: regressor.batches.iter().map(|batch| batch.name.clone()).collect(),
+        best_param,
+        best_cost: run.best_cost,
+        iterations: run.iterations,
+        termination: run.termination,
+        cancelled,
+        metric: regressor.param.metric.clone(),
+        residuals,
+        confidence,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// VCD simulated as 2 + t/10, measured off by +1, -1 and +2 at t = 0, 10 and 20
+    fn vcd_residuals() -> GroupResiduals {
+        let sim = SimulationResult {
+            time: vec![0., 10., 20.],
+            vcd: vec![2., 3., 4.],
+            ..SimulationResult::default()
+        };
+        let nodes = vec![
+            RegressorNode::new(Group::VCD, 0., 1.),
+            RegressorNode::new(Group::VCD, 10., 4.),
+            RegressorNode::new(Group::VCD, 20., 2.),
+        ];
+        let regressor = Regressor::single(nodes, Bioreactor::default(), Param::default(), SimSettings::default());
+        let mut residuals = regressor.residuals(0, &sim);
+        assert_eq!(residuals.len(), 1);
+        residuals.remove(0)
+    }
+
+    fn close(a: f64, b: f64) -> bool {
+        (a - b).abs() < 1e-12
+    }
+
+    #[test]
+    fn bounded_round_trip() {
+        let mut fit = FitTarget::new(parameter::by_path("mu_max"));
+        fit.lower = -2.;
+        fit.upper = 3.;
+        for x in [-1.999, -1., 0., 0.5, 2.9] {
+            let z = fit.to_internal(x);
+            assert!(z.is_finite());
+            assert!((fit.to_physical(z) - x).abs() < 1e-9, "{}", x);
+        }
+        // any optimizer value lands inside the bounds
+        for z in [-1e3, -10., 0., 10., 1e3] {
+            let x = fit.to_physical(z);
+            assert!(x >= fit.lower && x <= fit.upper, "{}", z);
+        }
+        // values at or beyond the bounds map to a finite value near the edge
+        assert!(fit.to_internal(-2.).is_finite() && fit.to_internal(7.).is_finite());
+    }
+
+    #[test]
+    fn equal_bounds_are_held_not_fitted() {
+        let mut param = Param::default();
+        let mut fixed = FitTarget::new(parameter::by_path("constants.kDO"));
+        fixed.lower = 2e-4;
+        fixed.upper = 2e-4;
+        param.targets.push(fixed);
+        let regressor = Regressor::single(Vec::new(), Bioreactor::default(), param, SimSettings::default());
+        assert_eq!(regressor.slots(), vec![Slot { fit: 0, batch: None }]);
+        let sims = regressor.apply(&[3e-4]);
+        assert_eq!(parameter::by_path("mu_max").get(&sims[0]), 3e-4);
+        assert_eq!(parameter::by_path("constants.kDO").get(&sims[0]), 2e-4);
+    }
+
+    #[test]
+    fn sse() {
+        let res = vcd_residuals();
+        assert_eq!(res.n, 3);
+        assert!(close(res.sse, 1. + 1. + 4.));
+        // measurements span 1 to 4, the default scaling divides by that range squared
+        assert!(close(res.cost(&Metric::Sse), 6. / 9.));
+    }
+
+    #[test]
+    fn weighted_sse() {
+        let res = vcd_residuals();
+        let expected = (1f64 / 1.).powi(2) + (-1f64 / 4.).powi(2) + (2f64 / 2.).powi(2);
+        assert!(close(res.weighted_sse, expected));
+        assert!(close(res.cost(&Metric::WeightedSse), expected));
+    }
+
+    #[test]
+    fn nrmse() {
+        let res = vcd_residuals();
+        assert!(close(res.nrmse, (6f64 / 3.).sqrt() / 3.));
+        assert!(close(res.cost(&Metric::Nrmse), res.nrmse));
+    }
+
+    #[test]
+    fn weighted_residuals_square_to_the_cost() {
+        let mut res = vcd_residuals();
+        res.weight = 2.5;
+        let squares = |metric: &Metric| res.weighted(metric).iter().map(|r| r.powi(2)).sum::<f64>();
+        assert!(close(squares(&Metric::Sse), res.cost(&Metric::Sse)));
+        assert!(close(squares(&Metric::WeightedSse), res.cost(&Metric::WeightedSse)));
+        // the least squares form of NRMSE is its square
+        assert!(close(squares(&Metric::Nrmse), 2.5 * res.nrmse.powi(2)));
+    }
+}
@@ -0,0 +1,395 @@
+use std::sync::{atomic::{AtomicBool, Ordering}, Arc};
+
+use rand::{seq::SliceRandom, Rng};
+
+use crate::{ensemble::simulate_each, model::Bioreactor, parameter::Parameter, regressor::Group, simulation::{SimSettings, SimulationResult}};
+
+/// Scalar summary of a simulation whose sensitivity is analysed
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Output {
+    FinalTiter,
+    PeakVcd,
+    GlucoseDay7,
+}
+impl Output {
+    pub const ALL: [Output; 3] = [Output::FinalTiter, Output::PeakVcd, Output::GlucoseDay7];
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            Output::FinalTiter => "final titer",
+            Output::PeakVcd => "peak VCD",
+            Output::GlucoseDay7 => "glucose at day 7",
+        }
+    }
+
+    /// `NaN` when the simulation does not reach the output
+    pub fn value(&self, res: &SimulationResult) -> f64 {
+        match self {
+            Output::FinalTiter => res.product.last().copied().unwrap_or(f64::NAN),
+            Output::PeakVcd => res.vcd.iter().copied().fold(f64::NAN, f64::max),
+            Output::GlucoseDay7 => res.sample(&res.glucose, 7. * 24. * 60.).unwrap_or(f64::NAN),
+        }
+    }
+}
+
+/// A sampled parameter and the range it is drawn from
+#[derive(Debug, Clone)]
+pub struct Factor {
+    pub parameter: &'static Parameter,
+    pub lower: f64,
+    pub upper: f64,
+}
+impl Factor {
+    /// half to double the current value, kept inside the parameter bounds
+    pub fn new(parameter: &'static Parameter, sim: &Bioreactor) -> Self {
+        let value = parameter.get(sim);
+        let (low, high) = parameter.bounds();
+        let (lower, upper) = if value > 0. { ((value / 2.).max(low), (value * 2.).min(high)) } else { (low, high) };
+        Self { parameter, lower, upper }
+    }
+
+    fn at(&self, u: f64) -> f64 {
+        self.lower + (self.upper - self.lower) * u
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Method {
+    /// elementary effects along random one-at-a-time trajectories on a grid of `levels`
+    Morris { trajectories: usize, levels: usize },
+    /// Saltelli sampling with `samples` base points
+    Sobol { samples: usize },
+}
+impl Method {
+    pub fn label(&self) -> &'static str {
+        match self {
+            Method::Morris { .. } => "Morris",
+            Method::Sobol { .. } => "Sobol",
+        }
+    }
+
+    /// names of the two indices reported per factor
+    pub fn indices(&self) -> [&'static str; 2] {
+        match self {
+            Method::Morris { .. } => ["μ*", "σ"],
+            Method::Sobol { .. } => ["first order", "total"],
+        }
+    }
+
+    pub fn runs(&self, factors: usize) -> usize {
+        match self {
+            Method::Morris { trajectories, .. } => trajectories * (factors + 1),
+            Method::Sobol { samples } => samples * (factors + 2),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct SensitivitySettings {
+    pub factors: Vec<Factor>,
+    pub method: Method,
+}
+impl SensitivitySettings {
+    pub fn default() -> Self {
+        Self {
+            factors: Vec::new(),
+            method: Method::Morris { trajectories: 10, levels: 4 },
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Sensitivity {
+    pub method: Method,
+    pub factors: Vec<&'static Parameter>,
+    /// two indices per factor for every output, named by `Method::indices`,
+    /// `None` when the output did not vary or no run of the factor finished
+    pub indices: Vec<(Output, Vec<Option<[f64; 2]>>)>,
+    pub runs: usize,
+    pub failed: usize,
+}
+
+/// grid levels and step of a Morris design,
+/// an even grid keeps every step of delta inside the unit interval
+fn morris_grid(levels: usize) -> (usize, f64) {
+    let levels = (levels.max(2) + 1) / 2 * 2;
+    (levels, levels as f64 / (2. * (levels - 1) as f64))
+}
+
+/// μ* and σ of the elementary effects of one factor, `None` without any effect
+pub fn morris_indices(effects: &[f64]) -> Option<[f64; 2]> {
+    if effects.is_empty() {
+        return None;
+    }
+    let n = effects.len() as f64;
+    let mu_star = effects.iter().map(|e| e.abs()).sum::<f64>() / n;
+    let mean = effects.iter().sum::<f64>() / n;
+    let sigma = (effects.iter().map(|e| (e - mean).powi(2)).sum::<f64>() / (n - 1.).max(1.)).sqrt();
+    Some([mu_star, sigma])
+}
+
+/// First order and total index of one factor from the outputs at the A and B samples
+/// and at A with the factor's column taken from B. Runs that did not finish are `NaN` and left out.
+/// `None` when the outputs do not vary or no row is complete.
+pub fn sobol_indices(ya: &[f64], yb: &[f64], yab: &[f64]) -> Option<[f64; 2]> {
+    let all: Vec<f64> = ya.iter().chain(yb).copied().filter(|y| y.is_finite()).collect();
+    let n = all.len() as f64;
+    let mean = all.iter().sum::<f64>() / n;
+    let variance = all.iter().map(|y| (y - mean).powi(2)).sum::<f64>() / n;
+    let rows: Vec<(f64, f64, f64)> = ya.iter().zip(yb).zip(yab).map(|((a, b), ab)| (*a, *b, *ab))
+        .filter(|(a, b, ab)| a.is_finite() && b.is_finite() && ab.is_finite()).collect();
+    if rows.is_empty() || variance <= 0. {
+        return None;
+    }
+    let m = rows.len() as f64;
+    // Saltelli (2010) for the first order, Jansen for the total index
+    let first = rows.iter().map(|(a, b, ab)| b * (ab - a)).sum::<f64>() / m / variance;
+    let total = rows.iter().map(|(a, _, ab)| (a - ab).powi(2)).sum::<f64>() / (2. * m) / variance;
+    Some([first, total])
+}
+
+/// Samples the factors of `settings` in `sim` and reports how much each one moves the outputs
+pub fn analyse<F>(sim: Bioreactor, sim_settings: SimSettings, settings: SensitivitySettings, cancel: Arc<AtomicBool>, mut progress: F) -> Result<Sensitivity, String>
+where
+    F: FnMut(u64),
+{
+    let k = settings.factors.len();
+    if k == 0 {
+        return Err("no factors to sample".to_string());
+    }
+    let mut rng = rand::thread_rng();
+    // every design point in the unit cube is laid out first, so the ensemble can run them all at once
+    let points: Vec<Vec<f64>> = match settings.method {
+        Method::Morris { trajectories, levels } => {
+            let (levels, delta) = morris_grid(levels);
+            let mut points = Vec::with_capacity(trajectories * (k + 1));
+            let mut order: Vec<usize> = (0..k).collect();
+            for _ in 0..trajectories {
+                let mut x: Vec<f64> = (0..k).map(|_| rng.gen_range(0..levels) as f64 / (levels - 1) as f64).collect();
+                points.push(x.clone());
+                order.shuffle(&mut rng);
+                for &i in &order {
+                    x[i] += if x[i] + delta <= 1. { delta } else { -delta };
+                    points.push(x.clone());
+                }
+            }
+            points
+        },
+        Method::Sobol { samples } => {
+            let a: Vec<Vec<f64>> = (0..samples).map(|_| (0..k).map(|_| rng.gen()).collect()).collect();
+            let b: Vec<Vec<f64>> = (0..samples).map(|_| (0..k).map(|_| rng.gen()).collect()).collect();
+            // then for each factor A with the factor's column taken from B
+            let mut points = Vec::with_capacity(samples * (k + 2));
+            points.extend(a.iter().cloned());
+            points.extend(b.iter().cloned());
+            for i in 0..k {
+                for (x, xb) in a.iter().zip(&b) {
+                    let mut x = x.clone();
+                    x[i] = xb[i];
+                    points.push(x);
+                }
+            }
+            points
+        },
+    };
+
+    let sims: Vec<Bioreactor> = points.iter().map(|u| {
+        let mut sim = sim.clone();
+        for (factor, u) in settings.factors.iter().zip(u) {
+            factor.parameter.set(&mut sim, factor.at(*u));
+        }
+        sim
+    }).collect();
+    let results = simulate_each(&sims, &sim_settings, &cancel, |run| progress(run as u64));
+    if cancel.load(Ordering::Relaxed) {
+        return Err("Cancelled".to_string());
+    }
+    let runs = results.len();
+    let mut failed = 0;
+    // every output of each design point
+    let y: Vec<Vec<f64>> = results.into_iter().map(|res| match res {
+        Some(Ok(res)) => Output::ALL.iter().map(|output| output.value(&res)).collect(),
+        _ => {
+            failed += 1;
+            vec![f64::NAN; Output::ALL.len()]
+        },
+    }).collect();
+
+    let per_output = match settings.method {
+        Method::Morris { levels, .. } => {
+            let (_, delta) = morris_grid(levels);
+            // effects[output][factor]
+            let mut effects = vec![vec![Vec::new(); k]; Output::ALL.len()];
+            for (trajectory, outputs) in points.chunks(k + 1).zip(y.chunks(k + 1)) {
+                for step in 1..=k {
+                    // exactly one coordinate moved between consecutive points
+                    let i = (0..k).find(|i| trajectory[step][*i] != trajectory[step - 1][*i]).unwrap_or(0);
+                    let step_size = if trajectory[step][i] > trajectory[step - 1][i] { delta } else { -delta };
+                    for (o, effect) in effects.iter_mut().enumerate() {
+                        let ee = (outputs[step][o] - outputs[step - 1][o]) / step_size;
+                        if ee.is_finite() {
+                            effect[i].push(ee);
+                        }
+                    }
+                }
+            }
+            effects.into_iter().map(|effect| effect.iter().map(|ee| morris_indices(ee)).collect()).collect::<Vec<Vec<_>>>()
+        },
+        Method::Sobol { samples } => {
+            let ya = &y[..samples];
+            let yb = &y[samples..2 * samples];
+            // yab[factor][sample]
+            let yab: Vec<&[Vec<f64>]> = y[2 * samples..].chunks(samples).collect();
+            (0..Output::ALL.len()).map(|o| {
+                let ya: Vec<f64> = ya.iter().map(|y| y[o]).collect();
+                let yb: Vec<f64> = yb.iter().map(|y| y[o]).collect();
+                yab.iter().map(|column| {
+                    let yab: Vec<f64> = column.iter().map(|y| y[o]).collect();
+                    sobol_indices(&ya, &yb, &yab)
+                }).collect()
+            }).collect()
+        },
+    };
+
+    Ok(Sensitivity {
+        method: settings.method.clone(),
+        factors: settings.factors.iter().map(|factor| factor.parameter).collect(),
+        indices: Output::ALL.iter().copied().zip(per_output).collect(),
+        runs,
+        failed,
+    })
+}
+
+/// ∂y/∂p of every measured group along the output grid, by central differences,
+/// one-sided where a step would leave the parameter bounds
+#[derive(Debug, Clone)]
+pub struct LocalSensitivity {
+    pub parameter: &'static Parameter,
+    pub value: f64,
+    pub time: Vec<f64>,
+    /// one curve per group in the order of `Group::ALL`
+    pub derivatives: Vec<Vec<f64>>,
+}
+impl LocalSensitivity {
+    /// `scaled` multiplies by the parameter value, the change for a 100% change of the parameter
+    pub fn series(&self, group: &Group, scaled: bool) -> Vec<[f64; 2]> {
+        let factor = if scaled { self.value } else { 1. };
+        self.time.iter().zip(&self.derivatives[group.index()]).map(|(t, d)| [*t, d * factor]).collect()
+    }
+}
+
+/// the values below and above `value` the difference is taken between
+fn local_steps(parameter: &Parameter, value: f64) -> (f64, f64) {
+    let (lower, upper) = parameter.bounds();
+    // 1% keeps the difference well above the solver tolerance
+    let h = (1e-2 * value.abs()).max(1e-4 * (upper - lower));
+    // a value already outside the bounds is not pushed further out
+    ((value - h).max(lower.min(value)), (value + h).min(upper.max(value)))
+}
+
+/// sensitivities of `parameters` around their current values in `sim`.
+/// A parameter whose runs fail gets its own error, the others are still computed.
+pub fn local<F>(sim: Bioreactor, settings: SimSettings, parameters: Vec<&'static Parameter>, cancel: Arc<AtomicBool>, mut progress: F) -> Result<Vec<Result<LocalSensitivity, String>>, String>
+where
+    F: FnMut(u64),
+{
+    let steps: Vec<(f64, f64)> = parameters.iter().map(|parameter| local_steps(parameter, parameter.get(&sim))).collect();
+    // the base run followed by the lower and upper run of every parameter
+    let mut sims = vec![sim.clone()];
+    for (parameter, (down, up)) in parameters.iter().zip(&steps) {
+        for val in [*down, *up] {
+            let mut sim = sim.clone();
+            parameter.set(&mut sim, val);
+            sims.push(sim);
+        }
+    }
+    let mut results = simulate_each(&sims, &settings, &cancel, |run| progress(run as u64)).into_iter();
+    if cancel.load(Ordering::Relaxed) {
+        return Err("Cancelled".to_string());
+    }
+    let base = match results.next() {
+        Some(Some(Ok(base))) => base,
+        Some(Some(Err(er))) => return Err(er.to_string()),
+        _ => return Err("Cancelled".to_string()),
+    };
+    let results: Vec<_> = results.collect();
+
+    Ok(parameters.iter().zip(&steps).zip(results.chunks(2)).map(|((parameter, (down, up)), pair)| {
+        if up <= down {
+            return Err(format!("{}: the bounds leave no room for a step", parameter.label()));
+        }
+        let (low, high) = match (&pair[0], &pair[1]) {
+            (Some(Ok(low)), Some(Ok(high))) => (low, high),
+            (Some(Err(er)), _) | (_, Some(Err(er))) => return Err(format!("{}: {}", parameter.label(), er)),
+            _ => return Err(format!("{}: cancelled", parameter.label())),
+        };
+        let derivatives = Group::ALL.iter().map(|group| base.time.iter().map(|t| {
+            match (low.sample(low.group(group), *t), high.sample(high.group(group), *t)) {
+                (Some(low), Some(high)) => (high - low) / (up - down),
+                _ => f64::NAN,
+            }
+        }).collect()).collect();
+        Ok(LocalSensitivity { parameter, value: parameter.get(&sim), time: base.time.clone(), derivatives })
+    }).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::{rngs::StdRng, SeedableRng};
+
+    use super::*;
+
+    fn close(a: f64, b: f64, tolerance: f64) -> bool {
+        (a - b).abs() < tolerance
+    }
+
+    #[test]
+    fn morris_of_linear_effects() {
+        let [mu_star, sigma] = morris_indices(&[-2., -2., -2.]).unwrap();
+        assert!(close(mu_star, 2., 1e-12) && close(sigma, 0., 1e-12));
+        // effects of opposite sign cancel in the mean but not in μ*
+        let [mu_star, sigma] = morris_indices(&[1., -1.]).unwrap();
+        assert!(close(mu_star, 1., 1e-12) && close(sigma, 2f64.sqrt(), 1e-12));
+        assert_eq!(morris_indices(&[]), None);
+    }
+
+    #[test]
+    fn sobol_of_an_additive_function() {
+        // y = x1 + 2 x2 + 0 x3 on the unit cube, Var = 1/12 + 4/12 so S1 = 0.2 and S2 = 0.8
+        let f = |x: &[f64]| x[0] + 2. * x[1];
+        let mut rng = StdRng::seed_from_u64(7);
+        let n = 20_000;
+        let a: Vec<Vec<f64>> = (0..n).map(|_| (0..3).map(|_| rng.gen()).collect()).collect();
+        let b: Vec<Vec<f64>> = (0..n).map(|_| (0..3).map(|_| rng.gen()).collect()).collect();
+        let ya: Vec<f64> = a.iter().map(|x| f(x)).collect();
+        let yb: Vec<f64> = b.iter().map(|x| f(x)).collect();
+        let expected = [0.2, 0.8, 0.];
+        for (i, expected) in expected.iter().enumerate() {
+            let yab: Vec<f64> = a.iter().zip(&b).map(|(x, xb)| {
+                let mut x = x.clone();
+                x[i] = xb[i];
+                f(&x)
+            }).collect();
+            let [first, total] = sobol_indices(&ya, &yb, &yab).unwrap();
+            // without interactions both indices agree
+            assert!(close(first, *expected, 0.02), "first order of x{}: {}", i + 1, first);
+            assert!(close(total, *expected, 0.02), "total of x{}: {}", i + 1, total);
+        }
+    }
+
+    #[test]
+    fn sobol_skips_failed_runs() {
+        let ya = [1., f64::NAN, 3., 2.];
+        let yb = [2., 1., f64::NAN, 4.];
+        let yab = [1., 2., 3., f64::NAN];
+        // only the first row is complete, the variance still uses every finite A and B output
+        let [first, total] = sobol_indices(&ya, &yb, &yab).unwrap();
+        assert!(close(first, 0., 1e-12) && close(total, 0., 1e-12));
+        assert_eq!(sobol_indices(&[f64::NAN; 2], &[f64::NAN; 2], &[1., 2.]), None);
+    }
+
+    #[test]
+    fn sobol_of_a_constant_output() {
+        assert_eq!(sobol_indices(&[5.; 4], &[5.; 4], &[5.; 4]), None);
+    }
+}
@@ -0,0 +1,195 @@
+use egui::{Color32, Slider};
+use egui_plot::LineStyle;
+use serde::{Deserialize, Serialize};
+
+use crate::{model::{Bioreactor, State, Time}, regressor::Group, solver::SolverError};
+
+pub const LACTATE_COLOR: Color32 = Color32::from_rgb(255, 140, 0);
+pub const AMMONIA_COLOR: Color32 = Color32::from_rgb(200, 100, 255);
+pub const TCD_COLOR: Color32 = Color32::LIGHT_RED;
+pub const VIABILITY_COLOR: Color32 = Color32::from_rgb(255, 105, 180);
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SimSettings {
+    pub duration: f64, // [day]
+    pub output_interval: f64, // [min]
+}
+impl SimSettings {
+    pub fn default() -> Self {
+        Self {
+            duration: 14.,
+            output_interval: 10.,
+        }
+    }
+
+    pub fn end(&self) -> Time {
+        self.duration * 24. * 60.
+    }
+
+    pub fn view(&mut self, ui: &mut egui::Ui) -> bool {
+        ui.add(Slider::new(&mut self.duration, 1.0..=60.).text("duration [day]")).changed() ||
+        ui.add(Slider::new(&mut self.output_interval, 1.0..=240.).logarithmic(true).text("output interval [min]")).changed() ||
+        false
+    }
+}
+
+/// One simulated quantity as it is drawn
+#[derive(Debug, Clone, Copy)]
+pub struct Trace<'a> {
+    pub name: &'static str,
+    pub values: &'a [f64],
+    pub color: Color32,
+    pub style: LineStyle,
+}
+
+/// Simulated time course sampled on the output grid of `SimSettings`
+#[derive(Debug, Clone, Default)]
+pub struct SimulationResult {
+    pub time: Vec<Time>,        // [min]
+    pub volume: Vec<f64>,       // [L]
+    pub vcd: Vec<f64>,          // [MVC/mL]
+    pub glucose: Vec<f64>,      // [g/L]
+    pub glutamine: Vec<f64>,    // [g/L]
+    pub c_o2: Vec<f64>,         // [mol/L]
+    pub dissolved_oxygen: Vec<f64>, // [%]
+    pub o2_flow: Vec<f64>,      // [L/min]
+    pub product: Vec<f64>,
+    pub lactate: Vec<f64>,      // [g/L]
+    pub ammonia: Vec<f64>,      // [g/L]
+    pub dead: Vec<f64>,         // [MVC/mL]
+    pub tcd: Vec<f64>,          // [MVC/mL]
+    pub viability: Vec<f64>,    // [%]
+    pub agitation: Vec<f64>,    // [W/m3]
+    pub air_flow: Vec<f64>,     // [L/min]
+}
+
+impl SimulationResult {
+    fn new(sim: &Bioreactor, time: Vec<Time>, states: &[State]) -> Self {
+        let column = |f: &dyn Fn(&State) -> f64| states.iter().map(f).collect::<Vec<f64>>();
+        let saturation = sim.oxigen_saturation();
+
+        Self {
+            volume: column(&|y| y[0]),
+            vcd: column(&|y| y[1]),
+            glucose: column(&|y| y[2]),
+            glutamine: column(&|y| y[3]),
+            c_o2: column(&|y| y[4]),
+            dissolved_oxygen: column(&|y| (y[4] / saturation) * 100.),
+            o2_flow: column(&|y| y[5]),
+            product: column(&|y| y[6]),
+            lactate: column(&|y| y[7]),
+            ammonia: column(&|y| y[8]),
+            dead: column(&|y| y[9]),
+            tcd: column(&|y| y[1] + y[9]),
+            // no cells at all reads as 0% rather than NaN
+            viability: column(&|y| if y[1] + y[9] > 0. { 100. * y[1] / (y[1] + y[9]) } else { 0. }),
+            agitation: column(&|y| y[12]),
+            air_flow: column(&|y| y[13] * sim.initial.volume),
+            time,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.time.is_empty()
+    }
+
+    /// every trace drawn on the main plot with its legend name and look
+    pub fn traces(&self) -> [Trace<'_>; 13] {
+        let solid = LineStyle::Solid;
+        [
+            Trace { name: "Volume", values: &self.volume, color: Color32::BLUE, style: solid },
+            Trace { name: "VCD", values: &self.vcd, color: Color32::RED, style: solid },
+            Trace { name: "Glucose", values: &self.glucose, color: Color32::GREEN, style: solid },
+            Trace { name: "Glutamin", values: &self.glutamine, color: Color32::YELLOW, style: solid },
+            Trace { name: "c_O2", values: &self.dissolved_oxygen, color: Color32::WHITE, style: solid },
+            Trace { name: "O2 input", values: &self.o2_flow, color: Color32::LIGHT_BLUE, style: solid },
+            Trace { name: "Agitation [W/m3]", values: &self.agitation, color: Color32::LIGHT_GRAY, style: LineStyle::dotted_dense() },
+            Trace { name: "Air flow [L/min]", values: &self.air_flow, color: Color32::from_rgb(135, 206, 235), style: LineStyle::dotted_dense() },
+            Trace { name: "Product", values: &self.product, color: Color32::GOLD, style: solid },
+            Trace { name: "Lactate", values: &self.lactate, color: LACTATE_COLOR, style: solid },
+            Trace { name: "Ammonia", values: &self.ammonia, color: AMMONIA_COLOR, style: solid },
+            Trace { name: "TCD", values: &self.tcd, color: TCD_COLOR, style: solid },
+            Trace { name: "Viability [%]", values: &self.viability, color: VIABILITY_COLOR, style: LineStyle::dashed_dense() },
+        ]
+    }
+
+    /// values paired with their time, ready for plotting
+    pub fn series(&self, values: &[f64]) -> Vec<[f64; 2]> {
+        self.time.iter().zip(values).map(|(t, y)| [*t, *y]).collect()
+    }
+
+    /// linear interpolation of `values` at time `t`, `None` outside the simulated horizon
+    pub fn sample(&self, values: &[f64], t: Time) -> Option<f64> {
+        let (first, last) = (*self.time.first()?, *self.time.last()?);
+        if t < first || t > last {
+            return None;
+        }
+        if self.time.len() == 1 {
+            return Some(values[0]);
+        }
+        let i = self.time.partition_point(|x| *x <= t).clamp(1, self.time.len() - 1);
+        let (x0, x1) = (self.time[i - 1], self.time[i]);
+        if x1 <= x0 {
+            return Some(values[i - 1]);
+        }
+        let w = (t - x0) / (x1 - x0);
+        Some(values[i - 1] + (values[i] - values[i - 1]) * w)
+    }
+
+    /// the simulated values a measurement group is compared against
+    pub fn group(&self, group: &Group) -> &[f64] {
+        match group {
+            Group::VCD => &self.vcd,
+            Group::Glucose => &self.glucose,
+            Group::Glutamin => &self.glutamine,
+            Group::DO => &self.dissolved_oxygen,
+            Group::Product => &self.product,
+            Group::Lactate => &self.lactate,
+            Group::Ammonia => &self.ammonia,
+            Group::TCD => &self.tcd,
+            Group::Viability => &self.viability,
+        }
+    }
+}
+
+/// Runs the bioreactor with its selected solver over the horizon of `settings`.
+/// Both the UI and the regressor go through here, so what is fitted is what is shown.
+pub fn simulate(sim: &Bioreactor, settings: &SimSettings) -> Result<SimulationResult, SolverError> {
+    let (time, states) = sim.solver.integrate(sim, sim.initial_state(), settings.end(), settings.output_interval)?;
+    Ok(SimulationResult::new(sim, time, &states))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn result() -> SimulationResult {
+        SimulationResult { time: vec![0., 10., 30.], ..SimulationResult::default() }
+    }
+
+    #[test]
+    fn sample_interpolates_between_grid_points() {
+        let res = result();
+        let values = [1., 3., -1.];
+        assert_eq!(res.sample(&values, 5.), Some(2.));
+        assert_eq!(res.sample(&values, 20.), Some(1.));
+        assert_eq!(res.sample(&values, 10.), Some(3.));
+    }
+
+    #[test]
+    fn sample_at_the_ends() {
+        let res = result();
+        let values = [1., 3., -1.];
+        assert_eq!(res.sample(&values, 0.), Some(1.));
+        assert_eq!(res.sample(&values, 30.), Some(-1.));
+    }
+
+    #[test]
+    fn sample_outside_the_horizon() {
+        let res = result();
+        let values = [1., 3., -1.];
+        assert_eq!(res.sample(&values, -1e-9), None);
+        assert_eq!(res.sample(&values, 30.5), None);
+        assert_eq!(SimulationResult::default().sample(&[], 0.), None);
+    }
+}
@@ -0,0 +1,261 @@
+use std::fmt::{self, Display, Formatter};
+
+use egui::Slider;
+use serde::{Deserialize, Serialize};
+
+use crate::model::{Bioreactor, State, Time, STATES};
+
+type Jacobian = nalgebra::SMatrix<f64, STATES, STATES>;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum Solver {
+    Rk4 { step: f64 },       // [min]
+    Dopri5 { rtol: f64, atol: f64 },
+    Dop853 { rtol: f64, atol: f64 },
+    /// linearly implicit, for stiff configurations such as a very high kLa
+    Rosenbrock { rtol: f64, atol: f64 },
+}
+
+#[derive(Debug)]
+pub enum SolverError {
+    Integration(String),
+    SingularMatrix(Time),
+    StepSizeTooSmall(Time),
+    MaxStepsReached(Time),
+}
+
+impl Display for SolverError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            SolverError::Integration(er) => write!(f, "integration failed: {}", er),
+            SolverError::SingularMatrix(x) => write!(f, "singular iteration matrix at {:.2} min", x),
+            SolverError::StepSizeTooSmall(x) => write!(f, "step size too small at {:.2} min", x),
+            SolverError::MaxStepsReached(x) => write!(f, "maximum number of steps reached at {:.2} min", x),
+        }
+    }
+}
+
+impl std::error::Error for SolverError {}
+
+impl Solver {
+    pub fn default() -> Self {
+        Solver::Rk4 { step: 2. }
+    }
+
+    pub const DEFAULTS: [(&'static str, Solver); 4] = [
+        ("RK4", Solver::Rk4 { step: 2. }),
+        ("Dopri5", Solver::Dopri5 { rtol: 1e-6, atol: 1e-10 }),
+        ("Dop853", Solver::Dop853 { rtol: 1e-6, atol: 1e-10 }),
+        ("Rosenbrock", Solver::Rosenbrock { rtol: 1e-4, atol: 1e-10 }),
+    ];
+
+    /// Integrates the bioreactor from `0` to `x_end`.
+    /// Output is reported every `dx` minutes, or at every step when `dx` is not positive.
+    pub fn integrate(&self, system: &Bioreactor, y0: State, x_end: Time, dx: Time) -> Result<(Vec<Time>, Vec<State>), SolverError> {
+        match *self {
+            Solver::Rk4 { step } => {
+                let mut stepper = ode_solvers::Rk4::new(system.clone(), 0., y0, x_end, step);
+                stepper.mut_integrate().map_err(|er| SolverError::Integration(format!("{:?}", er)))?;
+                if dx > 0. {
+                    Ok(resample(stepper.x_out(), stepper.y_out(), x_end, dx))
+                } else {
+                    Ok((stepper.x_out().clone(), stepper.y_out().clone()))
+                }
+            },
+            Solver::Dopri5 { rtol, atol } => {
+                let mut stepper = ode_solvers::Dopri5::new(system.clone(), 0., x_end, dx, y0, rtol, atol);
+                stepper.integrate().map_err(|er| SolverError::Integration(format!("{:?}", er)))?;
+                Ok((stepper.x_out().clone(), stepper.y_out().clone()))
+            },
+            Solver::Dop853 { rtol, atol } => {
+                let mut stepper = ode_solvers::Dop853::new(system.clone(), 0., x_end, dx, y0, rtol, atol);
+                stepper.integrate().map_err(|er| SolverError::Integration(format!("{:?}", er)))?;
+                Ok((stepper.x_out().clone(), stepper.y_out().clone()))
+            },
+            Solver::Rosenbrock { rtol, atol } => rosenbrock(system, y0, x_end, dx, rtol, atol),
+        }
+    }
+
+    pub fn view(&mut self, ui: &mut egui::Ui) -> bool {
+        let mut changed = false;
+        ui.horizontal(|ui| {
+            for (label, default) in Solver::DEFAULTS {
+                let selected = std::mem::discriminant(self) == std::mem::discriminant(&default);
+                if ui.selectable_label(selected, label).clicked() && !selected {
+                    *self = default;
+                    changed = true;
+                }
+            }
+        });
+        match self {
+            Solver::Rk4 { step } => {
+                changed |= ui.add(Slider::new(step, 0.1..=30.).logarithmic(true).text("step [min]")).changed();
+            },
+            Solver::Dopri5 { rtol, atol } | Solver::Dop853 { rtol, atol } | Solver::Rosenbrock { rtol, atol } => {
+                changed |= ui.add(Slider::new(rtol, 1e-12..=1e-2).logarithmic(true).text("relative tolerance")).changed();
+                changed |= ui.add(Slider::new(atol, 1e-14..=1e-4).logarithmic(true).text("absolute tolerance")).changed();
+            },
+        }
+        changed
+    }
+}
+
+const MAX_STEPS: usize = 100_000;
+const MAX_STEP_SIZE: Time = 30.; // [min] keeps feeding and shift switches from being stepped over
+
+/// ROS2 with an embedded first order error estimate, J and the time derivative are built by finite differences every step.
+fn rosenbrock<S: ode_solvers::System<Time, State>>(system: &S, y0: State, x_end: Time, dx: Time, rtol: f64, atol: f64) -> Result<(Vec<Time>, Vec<State>), SolverError> {    const GAMMA: f64 = 1. + std::f64::consts::FRAC_1_SQRT_2;
+
+    let f = |x: Time, y: &State| {
+        let mut dy = State::zeros();
+        system.system(x, y, &mut dy);
+        dy
+    };
+
+    let mut x = 0.;
+    let mut y = y0;
+    let mut h: f64 = 1.;
+    let mut x_out = vec![x];
+    let mut y_out = vec![y];
+
+    for _ in 0..MAX_STEPS {
+        if x >= x_end {
+            break;
+        }
+        h = h.min(MAX_STEP_SIZE).min(x_end - x);
+        if h < 1e-10 {
+            return Err(SolverError::StepSizeTooSmall(x));
+        }
+
+        let f0 = f(x, &y);
+        let mut jacobian = Jacobian::zeros();
+        for j in 0..STATES {
+            let delta = f64::EPSILON.sqrt() * y[j].abs().max(1e-6);
+            let mut y_delta = y;
+            y_delta[j] += delta;
+            let column = (f(x, &y_delta) - f0) / delta;
+            jacobian.set_column(j, &column);
+        }
+
+        // the time derivative keeps the order for forcing that changes with time, e.g. the temperature shift
+        let delta = f64::EPSILON.sqrt() * x.abs().max(1.);
+        let f_t = (f(x + delta, &y) - f0) / delta;
+
+        let lu = (Jacobian::identity() - jacobian * (GAMMA * h)).lu();
+        let k1 = lu.solve(&(f0 + f_t * (GAMMA * h))).ok_or(SolverError::SingularMatrix(x))?;
+        let k2 = lu.solve(&(f(x + h, &(y + k1 * h)) - k1 * 2. - f_t * (GAMMA * h))).ok_or(SolverError::SingularMatrix(x))?;
+
+        let y_new = y + k1 * (1.5 * h) + k2 * (0.5 * h);
+        let error = (k1 + k2) * (0.5 * h);
+
+        let err = (0..STATES).map(|i| {
+            let scale = atol + rtol * y[i].abs().max(y_new[i].abs());
+            (error[i] / scale).powi(2)
+        }).sum::<f64>() / STATES as f64;
+        let err = err.sqrt();
+
+        if err <= 1. {
+            x += h;
+            y = y_new;
+            x_out.push(x);
+            y_out.push(y);
+        }
+        h *= (0.9 * err.max(1e-10).powf(-0.5)).clamp(0.2, 5.);
+    }
+    if x < x_end {
+        return Err(SolverError::MaxStepsReached(x));
+    }
+
+    if dx > 0. {
+        Ok(resample(&x_out, &y_out, x_end, dx))
+    } else {
+        Ok((x_out, y_out))
+    }
+}
+
+/// linear interpolation of a trajectory onto an evenly spaced grid
+fn resample(x_out: &[Time], y_out: &[State], x_end: Time, dx: Time) -> (Vec<Time>, Vec<State>) {
+    let mut xs = Vec::new();
+    let mut ys = Vec::new();
+    let mut i = 0;
+    let n = (x_end / dx + 1e-9).floor() as usize;
+    for k in 0..=n {
+        let x = k as f64 * dx;
+        while i + 2 < x_out.len() && x_out[i + 1] < x {
+            i += 1;
+        }
+        let (x0, x1) = (x_out[i], x_out[(i + 1).min(x_out.len() - 1)]);
+        let t = if x1 > x0 { ((x - x0) / (x1 - x0)).clamp(0., 1.) } else { 0. };
+        xs.push(x);
+        ys.push(y_out[i] + (y_out[(i + 1).min(y_out.len() - 1)] - y_out[i]) * t);
+    }
+    (xs, ys)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// y' = -1000 (y - cos t) in the first state, the others stay put
+    struct Stiff;
+    impl ode_solvers::System<Time, State> for Stiff {
+        fn mut_system(&self, x: Time, y: &mut State, dy: &mut State) {
+            self.system(x, y, dy)
+        }
+        fn system(&self, x: Time, y: &State, dy: &mut State) {
+            dy.fill(0.);
+            dy[0] = -1000. * (y[0] - x.cos());
+        }
+    }
+
+    /// solution of `Stiff` from y(0) = 0
+    fn exact(t: f64) -> f64 {
+        let k: f64 = 1000.;
+        let d = k * k + 1.;
+        k * k / d * t.cos() + k / d * t.sin() - k * k / d * (-k * t).exp()
+    }
+
+    #[test]
+    fn rosenbrock_follows_a_stiff_solution() {
+        let (xs, ys) = rosenbrock(&Stiff, State::zeros(), 10., 0., 1e-4, 1e-6).unwrap();
+        assert_eq!(xs.last(), Some(&10.));
+        for (x, y) in xs.iter().zip(&ys) {
+            assert!((y[0] - exact(*x)).abs() < 1e-3, "y({}) = {}, expected {}", x, y[0], exact(*x));
+            assert_eq!(y[1], 0.);
+        }
+        // an explicit method would need more than 5000 steps below 2/1000
+        assert!(xs.len() < 1000, "{} steps", xs.len());
+    }
+
+    #[test]
+    fn rosenbrock_output_on_a_grid() {
+        let (xs, ys) = rosenbrock(&Stiff, State::zeros(), 10., 0.5, 1e-4, 1e-6).unwrap();
+        assert_eq!(xs.len(), 21);
+        for (x, y) in xs.iter().zip(&ys) {
+            assert!((y[0] - exact(*x)).abs() < 1e-3, "y({}) = {}, expected {}", x, y[0], exact(*x));
+        }
+    }
+
+    #[test]
+    fn resample_interpolates_a_non_uniform_grid() {
+        let x_out = [0., 0.5, 2., 2.25, 4.];
+        // y = 3x - 1 is linear, so interpolation has to be exact wherever the grid lands
+        let y_out: Vec<State> = x_out.iter().map(|x| State::from_element(3. * x - 1.)).collect();
+        let (xs, ys) = resample(&x_out, &y_out, 4., 1.);
+        assert_eq!(xs, vec![0., 1., 2., 3., 4.]);
+        for (x, y) in xs.iter().zip(&ys) {
+            assert!((y[0] - (3. * x - 1.)).abs() < 1e-12, "y({}) = {}", x, y[0]);
+            assert_eq!(y[0], y[STATES - 1]);
+        }
+    }
+
+    #[test]
+    fn resample_holds_the_last_value_past_the_end() {
+        let x_out = [0., 1., 2.5];
+        let y_out: Vec<State> = [0., 2., 5.].iter().map(|y| State::from_element(*y)).collect();
+        // the grid ends inside the last interval, 2.5 is not a multiple of 1
+        let (xs, ys) = resample(&x_out, &y_out, 2.5, 1.);
+        assert_eq!(xs, vec![0., 1., 2.]);
+        assert_eq!(ys.iter().map(|y| y[0]).collect::<Vec<_>>(), vec![0., 2., 4.]);
+    }
+}
@@ -0,0 +1,127 @@
+use nalgebra::DMatrix;
+use rayon::prelude::*;
+
+use crate::regressor::Regressor;
+
+/// Linearized uncertainty of fitted parameters, from the residual Jacobian at the optimum
+#[derive(Debug, Clone)]
+pub struct Confidence {
+    pub std_errors: Vec<f64>,
+    pub lower: Vec<f64>, // 95% interval
+    pub upper: Vec<f64>,
+    /// NaN where a standard error is zero and the correlation is undefined
+    pub correlation: Vec<Vec<f64>>,
+    pub dof: usize,
+    /// residual standard deviation
+    pub sigma: f64,
+}
+
+/// Jacobian of the weighted residuals by central differences, one column per target
+pub fn jacobian(regressor: &Regressor, vals: &[f64]) -> Result<DMatrix<f64>, String> {
+    let base = regressor.residual_vector(vals).map_err(|er| er.to_string())?;
+    // the columns are independent, so they are differentiated in parallel
+    let columns = regressor.slots().par_iter().enumerate().map(|(j, slot)| {
+        let fit = regressor.slot_target(slot);
+        let h = 1e-4 * vals[j].abs().max(1e-3 * (fit.upper - fit.lower).abs()).max(1e-12);
+        let (mut forward, mut backward) = (vals.to_vec(), vals.to_vec());
+        forward[j] += h;
+        backward[j] -= h;
+        let forward = regressor.residual_vector(&forward).map_err(|er| er.to_string())?;
+        let backward = regressor.residual_vector(&backward).map_err(|er| er.to_string())?;
+        if forward.len() != base.len() || backward.len() != base.len() {
+            return Err("measurements left the simulated horizon while differentiating".to_string());
+        }
+        Ok(forward.iter().zip(&backward).map(|(f, b)| (f - b) / (2. * h)).collect::<Vec<f64>>())
+    }).collect::<Result<Vec<_>, String>>()?;
+
+    let mut jacobian = DMatrix::zeros(base.len(), vals.len());
+    for (j, column) in columns.iter().enumerate() {
+        for (i, d) in column.iter().enumerate() {
+            jacobian[(i, j)] = *d;
+        }
+    }
+    Ok(jacobian)
+}
+
+/// Covariance s² (JᵀJ)⁻¹ with s² = SSR / (m - p), standard errors, t based 95% intervals and correlations.
+pub fn confidence(regressor: &Regressor, vals: &[f64]) -> Result<Confidence, String> {
+    let residuals = regressor.residual_vector(vals).map_err(|er| er.to_string())?;
+    let (m, p) = (residuals.len(), vals.len());
+    if m <= p {
+        return Err(format!("{} measurements are not enough for {} parameters", m, p));
+    }
+    let dof = m - p;
+    let sigma2 = residuals.iter().map(|r| r.powi(2)).sum::<f64>() / dof as f64;
+
+    let jacobian = jacobian(regressor, vals)?;
+    let information = jacobian.transpose() * &jacobian;
+    let covariance = information.try_inverse().ok_or("Fisher information is singular, the parameters are not identifiable from this data")? * sigma2;
+
+    let std_errors: Vec<f64> = (0..p).map(|i| covariance[(i, i)].max(0.).sqrt()).collect();
+    let t = t_975(dof);
+    let correlation = (0..p).map(|i| {
+        (0..p).map(|j| {
+            let scale = std_errors[i] * std_errors[j];
+            if scale > 0. { (covariance[(i, j)] / scale).clamp(-1., 1.) } else { f64::NAN }
+        }).collect()
+    }).collect();
+
+    Ok(Confidence {
+        lower: vals.iter().zip(&std_errors).map(|(v, se)| v - t * se).collect(),
+        upper: vals.iter().zip(&std_errors).map(|(v, se)| v + t * se).collect(),
+        std_errors,
+        correlation,
+        dof,
+        sigma: sigma2.sqrt(),
+    })
+}
+
+/// 97.5% quantiles of Student's t for 1 to 30 degrees of freedom
+const T_975: [f64; 30] = [
+    12.706204736, 4.302652730, 3.182446305, 2.776445105, 2.570581836,
+    2.446911851, 2.364624252, 2.306004135, 2.262157163, 2.228138852,
+    2.200985160, 2.178812830, 2.160368656, 2.144786688, 2.131449546,
+    2.119905299, 2.109815578, 2.100922040, 2.093024054, 2.085963447,
+    2.079613845, 2.073873068, 2.068657610, 2.063898562, 2.059538553,
+    2.055529439, 2.051830516, 2.048407142, 2.045229642, 2.042272456,
+];
+
+/// 97.5% quantile of Student's t, tabulated up to 30 degrees of freedom and from
+/// the Cornish-Fisher expansion around the normal quantile beyond, where it is accurate to 1e-6
+pub fn t_975(dof: usize) -> f64 {
+    if dof <= T_975.len() {
+        return T_975[dof.max(1) - 1];
+    }
+    const Z: f64 = 1.959963984540054;
+    let n = dof as f64;
+    let z3 = Z.powi(3);
+    let z5 = Z.powi(5);
+    let z7 = Z.powi(7);
+    let z9 = Z.powi(9);
+    Z + (z3 + Z) / (4. * n)
+        + (5. * z5 + 16. * z3 + 3. * Z) / (96. * n.powi(2))
+        + (3. * z7 + 19. * z5 + 17. * z3 - 15. * Z) / (384. * n.powi(3))
+        + (79. * z9 + 776. * z7 + 1482. * z5 - 1920. * z3 - 945. * Z) / (92160. * n.powi(4))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn t_quantiles() {
+        // from tables of Student's t
+        for (dof, t) in [(1, 12.7062), (2, 4.3027), (5, 2.5706), (10, 2.2281), (30, 2.0423), (31, 2.0395), (40, 2.0211), (60, 2.0003), (120, 1.9799), (1000, 1.9623)] {
+            assert!((t_975(dof) - t).abs() < 1e-4, "dof {}: {} instead of {}", dof, t_975(dof), t);
+        }
+        assert_eq!(t_975(0), t_975(1));
+        assert!((t_975(1_000_000) - 1.959964).abs() < 1e-5);
+    }
+
+    #[test]
+    fn t_quantiles_fall_with_dof() {
+        for dof in 1..200 {
+            assert!(t_975(dof + 1) < t_975(dof), "dof {}", dof);
+        }
+    }
+}
@@ -0,0 +1,116 @@
+use std::sync::{atomic::{AtomicBool, Ordering}, Arc};
+
+use crate::{ensemble::simulate_each, model::Bioreactor, parameter::{self, Parameter}, sensitivity::{Factor, Output}, simulation::{SimSettings, SimulationResult}};
+
+/// A swept parameter and its evenly spaced grid
+#[derive(Debug, Clone)]
+pub struct Axis {
+    pub parameter: &'static Parameter,
+    pub from: f64,
+    pub to: f64,
+    pub steps: usize,
+}
+impl Axis {
+    /// spans the same range a sensitivity factor would
+    pub fn new(parameter: &'static Parameter, sim: &Bioreactor) -> Self {
+        let factor = Factor::new(parameter, sim);
+        Self { parameter, from: factor.lower, to: factor.upper, steps: 5 }
+    }
+
+    pub fn values(&self) -> Vec<f64> {
+        match self.steps {
+            0 => Vec::new(),
+            1 => vec![self.from],
+            n => (0..n).map(|i| self.from + (self.to - self.from) * i as f64 / (n - 1) as f64).collect(),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct SweepSettings {
+    pub x: Axis,
+    /// a second axis turns the overlaid curves into a heatmap
+    pub y: Option<Axis>,
+}
+impl SweepSettings {
+    pub fn default() -> Self {
+        let parameter = parameter::by_path("feeding.rate");
+        let (from, to) = parameter.bounds();
+        Self {
+            x: Axis { parameter, from, to, steps: 5 },
+            y: None,
+        }
+    }
+
+    pub fn runs(&self) -> usize {
+        self.x.steps * self.y.as_ref().map(|y| y.steps).unwrap_or(1)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum Sweep {
+    /// a full time course for every value of the axis
+    Curves { parameter: &'static Parameter, runs: Vec<(f64, SimulationResult)>, failed: usize },
+    /// every output on the grid, `values[output][j * xs.len() + i]` for `xs[i]` and `ys[j]`
+    Heatmap { x: &'static Parameter, y: &'static Parameter, xs: Vec<f64>, ys: Vec<f64>, values: Vec<Vec<f64>>, failed: usize },
+}
+impl Sweep {
+    pub fn failed(&self) -> usize {
+        match self {
+            Sweep::Curves { failed, .. } | Sweep::Heatmap { failed, .. } => *failed,
+        }
+    }
+}
+
+/// Simulates `sim` at every point of the grid of `settings`
+pub fn sweep<F>(sim: Bioreactor, sim_settings: SimSettings, settings: SweepSettings, cancel: Arc<AtomicBool>, mut progress: F) -> Result<Sweep, String>
+where
+    F: FnMut(u64),
+{
+    let xs = settings.x.values();
+    let ys = settings.y.as_ref().map(|y| y.values()).unwrap_or_else(|| vec![f64::NAN]);
+    if xs.is_empty() || ys.is_empty() {
+        return Err("empty grid".to_string());
+    }
+    if settings.y.as_ref().map(|y| y.parameter == settings.x.parameter).unwrap_or(false) {
+        return Err(format!("{} is on both axes", settings.x.parameter.label()));
+    }
+    let mut sims = Vec::with_capacity(xs.len() * ys.len());
+    for y in &ys {
+        for x in &xs {
+            let mut sim = sim.clone();
+            settings.x.parameter.set(&mut sim, *x);
+            if let Some(axis) = &settings.y {
+                axis.parameter.set(&mut sim, *y);
+            }
+            sims.push(sim);
+        }
+    }
+
+    let results: Vec<Option<SimulationResult>> = simulate_each(&sims, &sim_settings, &cancel, |run| progress(run as u64))
+        .into_iter()
+        .map(|res| res.and_then(|res| res.ok()))
+        .collect();
+    if cancel.load(Ordering::Relaxed) {
+        return Err("Cancelled".to_string());
+    }
+    let failed = results.iter().filter(|res| res.is_none()).count();
+
+    Ok(match settings.y {
+        None => Sweep::Curves {
+            parameter: settings.x.parameter,
+            runs: xs.into_iter().zip(results).filter_map(|(x, res)| res.map(|res| (x, res))).collect(),
+            failed,
+        },
+        Some(axis) => Sweep::Heatmap {
+            x: settings.x.parameter,
+            y: axis.parameter,
+            values: Output::ALL.iter().map(|output| {
+                results.iter().map(|res| res.as_ref().map(|res| output.value(res)).unwrap_or(f64::NAN)).collect()
+            }).collect(),
+            xs,
+            ys,
+            failed,
+        },
+    })
+}
@@ -1,22 +1,25 @@
 use std::{fs::{self, File}, io::Write};
 
-use argmin::{core::Executor, solver::neldermead::NelderMead};
 use egui::Color32;
 use egui_plot::{HLine, Legend, Line, LineStyle, Plot, PlotPoints};
 use serde::{Deserialize, Serialize};
 
-use crate::{base::Graphs, model::{Bioreactor, State}, regressor::{Group, Mode, Param, RegressorNode, Target}};
-
-use super::{tree::{ParentNode, Tree}, Front};
-
-#[derive(Debug, Deserialize)]
-struct Record {
-    minutes: Option<f64>,
-    vcd: Option<f64>,
-    gln: Option<f64>,
-    gluc: Option<f64>,
-    do_50: Option<f64>,
-    product: Option<f64>
+use crate::{batch::Batch, import::ImportProfile, model::Bioreactor, parameter::PARAMETERS, regressor::{minimize, Fit, Group, Metric, Mode, Param, Regressor, RegressorBatch, RegressorNode}, simulation::{simulate, SimSettings, SimulationResult, AMMONIA_COLOR, LACTATE_COLOR, TCD_COLOR, VIABILITY_COLOR}, solver::SolverError};
+
+use super::{import::ImportDialog, mcmc::McmcTool, profile::ProfileTool, report, sensitivity::{LocalTool, SensitivityTool}, sweep::SweepTool, uncertainty::UncertaintyTool, tree::{ParentNode, Tree}, worker::{Progress, Worker}, Front};
+
+fn group_color(group: &Group) -> Color32 {
+    match group {
+        Group::VCD => Color32::RED,
+        Group::Glucose => Color32::GREEN,
+        Group::Glutamin => Color32::YELLOW,
+        Group::DO => Color32::WHITE,
+        Group::Product => Color32::GOLD,
+        Group::Lactate => LACTATE_COLOR,
+        Group::Ammonia => AMMONIA_COLOR,
+        Group::TCD => TCD_COLOR,
+        Group::Viability => VIABILITY_COLOR,
+    }
 }
 
 #[derive(Serialize, Debug, Deserialize)]
@@ -29,44 +32,240 @@ struct Output {
     DO: Option<f64>,
     c_O2: Option<f64>,
     oxygen: Option<f64>,
-    product: Option<f64>
+    product: Option<f64>,
+    lactate: Option<f64>,
+    ammonia: Option<f64>,
+    tcd: Option<f64>,
+    viability: Option<f64>,
+    agitation: Option<f64>,
+    air_flow: Option<f64>,
+}
+
+#[derive(Serialize, Debug)]
+struct ParameterRow {
+    path: &'static str,
+    value: f64,
+    unit: &'static str,
 }
 
 #[derive(Debug)]
 pub struct BionApp {
     sim: Bioreactor,
+    shared: Bioreactor, // what all batches run on before their overrides, kept in sync by `shared()`
     old_sim: Option<Bioreactor>,
-    sim_graphs: Graphs,
+    sim_result: SimulationResult,
+    sim_settings: SimSettings,
     point_nodes: Tree,
+    batches: Vec<Batch>, // empty while only one data set is loaded
+    active_batch: usize,
     selected_file: Option<String>,
     results: Option<String>,
     minimization_param: Param,
+    fit_job: Option<Worker<Result<Fit, String>>>,
+    fit_start: Option<Bioreactor>, // the simulation the running fit started from
+    fits: Vec<Fit>, // finished fits, compared side by side
+    profile: ProfileTool,
+    mcmc: McmcTool,
+    sensitivity: SensitivityTool,
+    local: LocalTool,
+    uncertainty: UncertaintyTool,
+    sweep: SweepTool,
+    import: Option<ImportDialog>,
+    import_profile: ImportProfile, // last mapping used, the starting point for the next file
+    simulation_job: Option<Worker<Result<SimulationResult, SolverError>>>,
+    sim_error: Option<String>,
+    sim_outdated: bool, // edited since the running simulation started
+    load_error: Option<String>, // last simulation file that could not be read
+    data_error: Option<String>, // last data file that could not be opened
+}
+
+/// Empty measurement tree with one parent node per regressor group
+fn data_tree() -> Tree {
+    Tree {
+        nodes: Group::ALL.iter().map(|group| ParentNode::new(group.to_string())).collect(),
+    }
 }
 
 impl Default for BionApp {
     fn default() -> Self {
         Self {
             sim: Bioreactor::default(),
+            shared: Bioreactor::default(),
             old_sim: None,
-            point_nodes: Tree {
-                nodes: vec![
-                    ParentNode::new(Group::VCD.to_string()),
-                    ParentNode::new(Group::Glucose.to_string()),
-                    ParentNode::new(Group::Glutamin.to_string()),
-                    ParentNode::new(Group::Product.to_string()),
-                    ParentNode::new(Group::DO.to_string()),
-                ],
-            },
-            sim_graphs: Graphs::default(),
+            point_nodes: data_tree(),
+            batches: Vec::new(),
+            active_batch: 0,
+            sim_result: SimulationResult::default(),
+            sim_settings: SimSettings::default(),
             selected_file: None,
             results: None,
             minimization_param: Param::default(),
+            fit_job: None,
+            fit_start: None,
+            fits: Vec::new(),
+            profile: ProfileTool::default(),
+            mcmc: McmcTool::default(),
+            sensitivity: SensitivityTool::default(),
+            local: LocalTool::default(),
+            uncertainty: UncertaintyTool::default(),
+            sweep: SweepTool::default(),
+            import: None,
+            import_profile: ImportProfile::default(),
+            simulation_job: None,
+            sim_error: None,
+            sim_outdated: false,
+            load_error: None,
+            data_error: None,
             
         }
     }
 }
 
 
+impl BionApp {
+    /// regressor for the loaded measurements and the current minimization settings
+    fn regressor(&self) -> Regressor {
+        if self.batches.len() < 2 {
+            return Regressor::single(
+                RegressorNode::translate(self.point_nodes.clone()),
+                self.sim.clone(),
+                self.minimization_param.clone(),
+                self.sim_settings.clone(),
+            );
+        }
+        let shared = self.shared();
+        let batches = self.synced_batches().into_iter().map(|batch| RegressorBatch {
+            simulation: batch.bioreactor(&shared),
+            nodes: RegressorNode::translate(batch.nodes),
+            name: batch.name,
+        }).collect();
+        Regressor {
+            batches,
+            param: self.minimization_param.clone(),
+            settings: self.sim_settings.clone(),
+        }
+    }
+
+    /// batches with the edits to the shown one included
+    fn synced_batches(&self) -> Vec<Batch> {
+        let mut batches = self.batches.clone();
+        if let Some(batch) = batches.get_mut(self.active_batch) {
+            batch.store(&self.point_nodes, &self.sim);
+        }
+        batches
+    }
+
+    /// the shown bioreactor with the overrides of the shown batch taken back out
+    fn shared(&self) -> Bioreactor {
+        let mut shared = self.sim.clone();
+        if let Some(batch) = self.batches.get(self.active_batch) {
+            for (target, _) in &batch.overrides {
+                target.set(&mut shared, target.get(&self.shared));
+            }
+        }
+        shared
+    }
+
+    fn switch_batch(&mut self, i: usize) {
+        self.shared = self.shared();
+        self.batches = self.synced_batches();
+        self.show_batch(i);
+    }
+
+    /// shows batch `i` without storing the edits to the current one
+    fn show_batch(&mut self, i: usize) {
+        self.active_batch = i;
+        self.point_nodes = self.batches[i].nodes.clone();
+        self.sim = self.batches[i].bioreactor(&self.shared);
+    }
+
+    /// shows a whole new bioreactor, every batch runs on it without the values fitted to it alone
+    fn replace_sim(&mut self, sim: Bioreactor) {
+        self.shared = sim.clone();
+        self.sim = sim;
+        for batch in self.batches.iter_mut() {
+            batch.overrides.clear();
+        }
+    }
+
+    /// writes fitted values back, per-batch ones to their batch
+    fn apply_fit(&mut self, fit_index: usize) {
+        let fit = &self.fits[fit_index];
+        let best = match &fit.best_param {
+            Some(best) => best,
+            None => return,
+        };
+        self.shared = self.shared();
+        for ((target, batch), val) in fit.targets.iter().zip(best) {
+            match batch {
+                Some(b) => {
+                    if let Some(batch) = self.batches.get_mut(*b) {
+                        batch.set_override(target, *val);
+                    }
+                    if *b == self.active_batch {
+                        target.set(&mut self.sim, *val);
+                    }
+                },
+                None => {
+                    target.set(&mut self.sim, *val);
+                    target.set(&mut self.shared, *val);
+                    for batch in self.batches.iter_mut() {
+                        batch.clear_override(target);
+                        batch.update(&self.shared, target, *val);
+                    }
+                },
+            }
+        }
+    }
+
+    fn batches_view(&mut self, ui: &mut egui::Ui) -> bool {
+        let mut changed = false;
+        let mut switch = None;
+        let mut remove = None;
+        let active = self.active_batch;
+        for (i, batch) in self.batches.iter_mut().enumerate() {
+            ui.horizontal(|ui| {
+                if ui.selectable_label(i == active, format!("{}", i + 1)).clicked() && i != active {
+                    switch = Some(i);
+                }
+                ui.text_edit_singleline(&mut batch.name);
+                if ui.button("Remove").clicked() {
+                    remove = Some(i);
+                }
+            });
+        }
+        if let Some(i) = switch {
+            self.switch_batch(i);
+            changed = true;
+        }
+        if let Some(i) = remove {
+            self.shared = self.shared();
+            self.batches = self.synced_batches();
+            self.batches.remove(i);
+            if self.batches.is_empty() {
+                self.active_batch = 0;
+            } else {
+                let active = if self.active_batch > i || self.active_batch == self.batches.len() { self.active_batch - 1 } else { self.active_batch };
+                self.show_batch(active);
+                changed = true;
+            }
+        }
+        if ui.button("Add batch").clicked() {
+            if self.batches.is_empty() {
+                let name = self.selected_file.as_ref()
+                    .and_then(|path| path.split("/").last().map(|name| name.to_string()))
+                    .unwrap_or("Batch 1".to_string());
+                self.batches.push(Batch::new(name, self.point_nodes.clone(), &self.sim));
+            }
+            let name = format!("Batch {}", self.batches.len() + 1);
+            self.batches.push(Batch::new(name, data_tree(), &self.sim));
+            self.switch_batch(self.batches.len() - 1);
+            changed = true;
+        }
+        changed
+    }
+}
+
 impl Front for BionApp {
     fn left_panel(&mut self, ui: &mut egui::Ui, ctx: &egui::Context) {
         egui::ScrollArea::vertical().show(ui, |ui| {
@@ -87,19 +286,21 @@ impl Front for BionApp {
                 if (ui.button("Load Simulation")).clicked() {
 
                     if let Some(path) = rfd::FileDialog::new().pick_file() {
-                        if let Ok(content) = fs::read_to_string(path) {
-                            if let Ok(sim)  = serde_json::from_str::<Bioreactor>(&content) {
+                        let loaded = fs::read_to_string(path).map_err(|er| er.to_string())
+                            .and_then(|content| serde_json::from_str::<Bioreactor>(&content).map_err(|er| er.to_string()));
+                        match loaded {
+                            Ok(mut sim) => {
+                                sim.upgrade();
                                 self.old_sim = Some(self.sim.clone());
-                                self.sim = sim;
+                                self.replace_sim(sim);
+                                self.load_error = None;
                                 sim_changed = true;
-                            }
-
-
-                        } else {
-                            println!("Error reading file");
+                            },
+                            Err(er) => {
+                                println!("Error reading simulation: {}", er);
+                                self.load_error = Some(er);
+                            },
                         }
-
-                        
                     }
                 }
 
@@ -116,6 +317,9 @@ impl Front for BionApp {
                     self.old_sim = olds;
                 }
             });
+            if let Some(er) = &self.load_error {
+                ui.colored_label(ui.visuals().warn_fg_color, format!("Could not load simulation: {}", er));
+            }
 
 
 
@@ -126,75 +330,57 @@ impl Front for BionApp {
             sim_changed = sim_changed || ui.collapsing("Simulation", |ui| {
                 self.sim.view(ui)
             }).body_returned.unwrap_or(false);
+            sim_changed = sim_changed || ui.collapsing("Horizon", |ui| {
+                self.sim_settings.view(ui)
+            }).body_returned.unwrap_or(false);
 
             ui.separator();
             ui.label("input data");
             ui.horizontal(|ui|{
                 if (ui.button("Load data")).clicked() {
-
                     if let Some(path) = rfd::FileDialog::new().pick_file() {
                         self.selected_file = Some(path.display().to_string());
-                        let file = 
-                        if let Ok(content) = fs::read_to_string(path) {
-                            let mut rdr = csv::Reader::from_reader(content.as_bytes());
-                            for result in rdr.deserialize() {
-                                if let Ok(res) = result {
-                                    let record: Record = res;
-                                    if let Some(minute) = record.minutes {
-                                        if let Some(vcd) = record.vcd {
-                                            self.point_nodes.add("VCD".to_string(), minute, vcd);
-                                        }
-                                        if let Some(gln) = record.gln {
-                                            self.point_nodes.add("Glutamin".to_string(), minute, gln);
-                                        }
-                                        if let Some(gluc) = record.gluc {
-                                            self.point_nodes.add("Glucose".to_string(), minute, gluc);
-                                        }
-                                        if let Some(oxygen) = record.do_50 {
-                                            self.point_nodes.add("DO".to_string(), minute, oxygen);
-                                        }
-                                        if let Some(product) = record.product {
-                                            self.point_nodes.add("Product".to_string(), minute, product);
-                                        }
-                                    }
-                                }
-                            }
-                        };
+                        match ImportDialog::open(path, &self.import_profile) {
+                            Ok(dialog) => {
+                                self.import = Some(dialog);
+                                self.data_error = None;
+                            },
+                            Err(er) => {
+                                println!("Error reading file: {}", er);
+                                self.data_error = Some(er);
+                            },
+                        }
                     }
                 }
 
-                
-
                 if ui.button("Clear Nodes").clicked() {
-                    self.point_nodes = Tree {
-                        nodes: vec![
-                            ParentNode::new(Group::VCD.to_string()),
-                            ParentNode::new(Group::Glucose.to_string()),
-                            ParentNode::new(Group::Glutamin.to_string()),
-                            ParentNode::new(Group::Product.to_string()),
-                            ParentNode::new(Group::DO.to_string()),
-                        ],
-                    };
+                    self.point_nodes = data_tree();
                 }
                 if ui.button("Export data").clicked() {
                     if let Some(path) = rfd::FileDialog::new().save_file() {
                         let selected_file_export = path.display().to_string();
-                        let Graphs { volume, vcd, glucose, glutamin, c_O2, O2, product } = self.sim_graphs.clone();
+                        let res = &self.sim_result;
                         
                         
                         if let Ok(mut wrt)  = csv::Writer::from_path(path.clone()) {
-                            for (i, [x,y]) in vcd.into_iter().enumerate() {
+                            for (i, x) in res.time.iter().enumerate() {
                                 let row = Output {
-                                    minutes: Some(x),
-                                    volume: Some(volume[i][1]),
-                                    vcd: Some(y),
-                                    glutamin: Some(glutamin[i][1]),
-                                    glucose: Some(glucose[i][1]),
+                                    minutes: Some(*x),
+                                    volume: Some(res.volume[i]),
+                                    vcd: Some(res.vcd[i]),
+                                    glutamin: Some(res.glutamine[i]),
+                                    glucose: Some(res.glucose[i]),
                                     
-                                    DO: Some((c_O2[i][1] / self.sim.oxigen_saturation()) * 100.),
-                                    c_O2: Some(c_O2[i][1]),
-                                    oxygen: Some(O2[i][1]),
-                                    product: Some(product[i][1]),
+                                    DO: Some(res.dissolved_oxygen[i]),
+                                    c_O2: Some(res.c_o2[i]),
+                                    oxygen: Some(res.o2_flow[i]),
+                                    product: Some(res.product[i]),
+                                    lactate: Some(res.lactate[i]),
+                                    ammonia: Some(res.ammonia[i]),
+                                    tcd: Some(res.tcd[i]),
+                                    viability: Some(res.viability[i]),
+                                    agitation: Some(res.agitation[i]),
+                                    air_flow: Some(res.air_flow[i]),
                                 };
                                 if let Err(e) = wrt.serialize(row) {
                                     println!("there was an error while writing: {:?}", e);
@@ -205,6 +391,19 @@ impl Front for BionApp {
                                 println!("err: {:?}", err);
                             }
                         }
+                        let mut param_path = path.clone();
+                        param_path.set_extension("parameters.csv");
+                        if let Ok(mut wrt) = csv::Writer::from_path(param_path) {
+                            for param in PARAMETERS.iter() {
+                                let row = ParameterRow { path: param.path(), value: param.get(&self.sim), unit: param.unit() };
+                                if let Err(e) = wrt.serialize(row) {
+                                    println!("there was an error while writing: {:?}", e);
+                                }
+                            }
+                            if let Err(err) = wrt.flush() {
+                                println!("err: {:?}", err);
+                            }
+                        }
                         if let Ok(sim_json) = serde_json::to_string_pretty(&self.sim) {
                             let mut sim_path = path;
                             sim_path.set_extension("json");
@@ -218,28 +417,79 @@ impl Front for BionApp {
                     }
                 }
             });
+            if let Some(er) = &self.data_error {
+                ui.colored_label(ui.visuals().warn_fg_color, format!("Could not open data: {}", er));
+            }
 
             
             
             
+            if let Some(dialog) = &mut self.import {
+                let mut open = true;
+                if let Some(imports) = dialog.show(ctx, &mut open) {
+                    let mut imports = imports.into_iter();
+                    if let Some((name, import)) = imports.next() {
+                        if let Some(batch) = self.batches.get_mut(self.active_batch) {
+                            batch.name = name.clone();
+                        }
+                        // the first sheet takes the place of the shown data
+                        self.point_nodes = data_tree();
+                        for (group, t, y) in import.points {
+                            self.point_nodes.add(group.to_string(), t, y);
+                        }
+                        // further sheets of a workbook become batches of their own
+                        for (sheet, import) in imports {
+                            if self.batches.is_empty() {
+                                self.batches.push(Batch::new(name.clone(), self.point_nodes.clone(), &self.sim));
+                            }
+                            let mut nodes = data_tree();
+                            for (group, t, y) in import.points {
+                                nodes.add(group.to_string(), t, y);
+                            }
+                            self.batches.push(Batch::new(sheet, nodes, &self.sim));
+                        }
+                    }
+                    self.import_profile = dialog.profile.clone();
+                }
+                if !open {
+                    self.import = None;
+                }
+            }
             if let Some(path) = &self.selected_file {
                 ui.horizontal(|ui| {
                     ui.label("Selected file:");
                     ui.monospace(path.split("/").last().unwrap_or("None"));
                 });
             }
+            sim_changed = sim_changed || ui.collapsing("Batches", |ui| {
+                self.batches_view(ui)
+            }).body_returned.unwrap_or(false);
             ui.separator();
-            ui.label("Minimization Target");
-            ui.horizontal(|ui| {
-                
-                ui.selectable_value(&mut self.minimization_param.target, Target::MuMax, "mu max");
-                ui.selectable_value(&mut self.minimization_param.target, Target::NVcd, "n_vcd");
-                ui.selectable_value(&mut self.minimization_param.target, Target::FeedRate, "Feed rate");
-                ui.selectable_value(&mut self.minimization_param.target, Target::Glucose, "Glucose");
-                ui.selectable_value(&mut self.minimization_param.target, Target::Glutamin, "Glutamin");
-                ui.selectable_value(&mut self.minimization_param.target, Target::DO, "DO");
-                ui.selectable_value(&mut self.minimization_param.target, Target::Product, "Product");
+            ui.label("Minimization Targets");
+            let mut toggled = None;
+            ui.horizontal_wrapped(|ui| {
+                for fit in &self.minimization_param.targets {
+                    let mut selected = true;
+                    if ui.checkbox(&mut selected, fit.target.label()).on_hover_text(fit.target.path()).changed() {
+                        toggled = Some(fit.target);
+                    }
+                }
+                egui::ComboBox::from_id_source("add target").selected_text("add target").show_ui(ui, |ui| {
+                    for target in PARAMETERS.iter().filter(|target| !self.minimization_param.contains(target)) {
+                        if ui.selectable_label(false, format!("{} [{}]", target.path(), target.unit())).clicked() {
+                            toggled = Some(target);
+                        }
+                    }
+                });
             });
+            if let Some(target) = toggled {
+                self.minimization_param.toggle(target);
+            }
+            if !self.minimization_param.targets.is_empty() {
+                ui.collapsing("Bounds", |ui| {
+                    self.minimization_param.bounds_view(ui, &self.sim);
+                });
+            }
             ui.separator();
 
             ui.label("Minimization mode");
@@ -252,116 +502,171 @@ impl Front for BionApp {
                 ui.selectable_value(&mut self.minimization_param.mode, Mode::Single(Group::Glutamin), "Glutamin");
                 ui.selectable_value(&mut self.minimization_param.mode, Mode::Single(Group::DO), "DO");
                 ui.selectable_value(&mut self.minimization_param.mode, Mode::Single(Group::Product), "Product");
+                ui.selectable_value(&mut self.minimization_param.mode, Mode::Single(Group::Lactate), "Lactate");
+                ui.selectable_value(&mut self.minimization_param.mode, Mode::Single(Group::Ammonia), "Ammonia");
+                ui.selectable_value(&mut self.minimization_param.mode, Mode::Single(Group::TCD), "TCD");
+                ui.selectable_value(&mut self.minimization_param.mode, Mode::Single(Group::Viability), "Viability");
                     //}
             });
+            ui.horizontal(|ui| {
+                ui.label("Residual metric");
+                for metric in Metric::ALL {
+                    let label = metric.label();
+                    ui.selectable_value(&mut self.minimization_param.metric, metric, label);
+                }
+            });
+            ui.collapsing("Group weights", |ui| {
+                self.minimization_param.weighting.view(ui);
+            });
+            ui.collapsing("Optimizer", |ui| {
+                self.minimization_param.optimizer.view(ui);
+            });
             ui.separator();
 
 
 
 
-            if ui.button("Minimize").clicked() {
-
-                self.results = Some("Calculating...".to_string());
-
-                let cost = crate::regressor::Regressor {
-                    nodes: RegressorNode::translate(self.point_nodes.clone()),
-                    simulation: self.sim.clone(),
-                    param: self.minimization_param.clone(),
-                    epsilon: 1e-1,
-                };
-
-                let initial_points = match self.minimization_param.target {
-                    Target::MuMax => vec![1e-10, 0.9999999999],
-                    Target::NVcd => vec![1e-10, 0.9999999999],
-                    Target::FeedRate => vec![1e-10, 0.9999999999],
-                    Target::Glucose => vec![1e-10, 0.5],
-                    Target::Glutamin => vec![1e-10, 0.9999999999],
-                    Target::Product => vec![1e-10, 0.9999999999],
-                    Target::DO => vec![1e-10, 0.9999999999],
-                };
+            let fitting = self.fit_job.is_some();
+            if ui.add_enabled(!fitting && !self.minimization_param.targets.is_empty(), egui::Button::new("Minimize")).clicked() {
 
-                let solver = NelderMead::new(initial_points)
-                .with_sd_tolerance(1e-5).unwrap();
+                let cost = self.regressor();
 
-                let res = Executor::new(cost, solver)
-                .configure(|state| state.max_iters(1000))
-                .run();
-
-            
-                
-                let result = match res {
-                    Ok(val) => {
-
-                        if let Some(p) = val.state.best_param {
-                            self.sim.update( &self.minimization_param, p);
+                self.results = None;
+                self.fit_start = Some(self.sim.clone());
+                self.fit_job = Some(Worker::spawn(ctx, move |reporter| {
+                    let cancel = reporter.cancel_flag();
+                    minimize(cost, cancel, move |iter, best_cost| reporter.progress(iter, best_cost))
+                        .map_err(|er| er.to_string())
+                }));
+            }
 
+            if let Some(job) = &mut self.fit_job {
+                let finished = job.show(ui, |Progress { iter, value }| format!("iteration {}, best cost {:.6e}", iter, value));
+
+                if let Some(res) = finished {
+                    self.fit_job = None;
+                    let unchanged = self.fit_start.take().as_ref() == Some(&self.sim);
+                    match res.and_then(|fit| fit) {
+                        Ok(fit) => {
+                            let cancelled = fit.cancelled;
+                            self.fits.push(fit);
+                            if cancelled {
+                                self.results = Some("The fit was cancelled, so it was not applied.\nApply it from the fit comparison to use the best point found so far.".to_string());
+                            } else if unchanged {
+                                self.apply_fit(self.fits.len() - 1);
+                                sim_changed = true;
+                                self.results = None;
+                            } else {
+                                self.results = Some("The simulation was edited while fitting, so the fit was not applied.\nApply it from the fit comparison to overwrite the edits.".to_string());
+                            }
+                        },
+                        Err(er) => {
+                            self.results = Some(format!("Something went wrong: \n{}", er));
                         }
-                        sim_changed = true;
-
-                        format!("State: {:?}\n Best: {:?}", val.state, val.state.best_param)
-                    },
-                    Err(er) => {
-                        format!("Something went wrong: \nState: {:?}", er )
                     }
-                };
-
-                self.results = Some(result);
+                }
             }
             if let Some(result) = &self.results {
                 ui.label(result);
             }
+            if let Some(fit) = self.fits.last() {
+                ui.collapsing("Fit report", |ui| report::fit_report(ui, fit));
+            }
+            if !self.fits.is_empty() {
+                ui.collapsing("Fit comparison", |ui| {
+                    let mut apply = None;
+                    egui::Grid::new("fit comparison").striped(true).num_columns(5).show(ui, |ui| {
+                        ui.strong("optimizer");
+                        ui.strong("targets");
+                        ui.strong("best cost");
+                        ui.strong("iterations");
+                        ui.label("");
+                        ui.end_row();
+                        let best = self.fits.iter().map(|fit| fit.best_cost).fold(f64::INFINITY, f64::min);
+                        for (i, fit) in self.fits.iter().enumerate() {
+                            ui.label(fit.optimizer);
+                            ui.label(fit.labels.join(", "));
+                            let cost = format!("{:.6e} ({})", fit.best_cost, fit.metric.label());
+                            if fit.best_cost == best { ui.strong(cost) } else { ui.label(cost) };
+                            ui.label(fit.iterations.to_string());
+                            if ui.add_enabled(fit.best_param.is_some(), egui::Button::new("Apply")).clicked() {
+                                apply = Some(i);
+                            }
+                            ui.end_row();
+                        }
+                    });
+                    if let Some(i) = apply {
+                        self.apply_fit(i);
+                        sim_changed = true;
+                    }
+                    if ui.button("Clear").clicked() {
+                        self.fits.clear();
+                    }
+                });
+            }
+            ui.collapsing("Profile likelihood", |ui| {
+                let regressor = self.regressor();
+                self.profile.view(ui, ctx, &regressor);
+            });
+            ui.collapsing("Bayesian estimation", |ui| {
+                let regressor = self.regressor();
+                self.mcmc.view(ui, ctx, &regressor);
+            });
+            ui.collapsing("Sensitivity", |ui| {
+                self.sensitivity.view(ui, ctx, &self.sim, &self.sim_settings);
+            });
+            ui.collapsing("Local sensitivity", |ui| {
+                self.local.view(ui, ctx, &self.sim, &self.sim_settings);
+            });
+            ui.collapsing("Monte Carlo uncertainty", |ui| {
+                self.uncertainty.view(ui, ctx, &self.sim, &self.sim_settings);
+            });
+            ui.collapsing("Parameter sweep", |ui| {
+                self.sweep.view(ui, ctx, &self.sim, &self.sim_settings);
+            });
 
 
-            if sim_changed || self.sim_graphs.vcd.is_empty() {
-                
-                const MINUTES: f64 = 14. * 24. * 60.;
-
-                const STEP: f64 = 2.; // step increment lower is more precise but more computationaly intense
-                
-                let initial_state = State::from([
-                    self.sim.initial.volume, 
-                    self.sim.initial.vcd, 
-                    self.sim.initial.glucose, 
-                    self.sim.initial.glutamine, 
-                    (self.sim.initial.oxygen_part * self.sim.oxigen_saturation()) / 100., 
-                    0., 
-                    0. 
-                ]);
-                    self.old_sim = Some(last_state);
-                let mut stepper = ode_solvers::Rk4::new(self.sim.clone(), 0., initial_state, MINUTES, STEP);
-
-                let res = stepper.mut_integrate();
-                if let Ok(_val) = res {
-
-                    let Graphs { volume, vcd, glucose, glutamin, c_O2, O2, product } = &mut self.sim_graphs;
-
-                    *volume = Vec::new();
-                    *vcd = Vec::new();
-                    *glucose = Vec::new();
-                    *glutamin = Vec::new();
-                    *c_O2 = Vec::new();
-                    *O2 = Vec::new();
-                    *product = Vec::new();
-                    for (t,y) in stepper.x_out().iter().zip(stepper.y_out()) {
-
-                        volume.push([*t, y[0] ]);
-                        vcd.push([*t, y[1] ]);
-                        glucose.push([*t, y[2] ]);
-                        glutamin.push([*t, y[3] ]);
-                        c_O2.push([*t, y[4] ]);
-                        O2.push([*t, y[5] ]);
-                        product.push([*t, y[6] ]);
-                        
+            if sim_changed {
+                self.old_sim = Some(last_state);
+                self.sim_outdated = true;
+            }
+            // one simulation at a time, edits made while it runs start the next one when it is done
+            if self.simulation_job.is_none() && (self.sim_outdated || (self.sim_result.is_empty() && self.sim_error.is_none())) {
+                self.sim_outdated = false;
+                let sim = self.sim.clone();
+                let settings = self.sim_settings.clone();
+                self.simulation_job = Some(Worker::spawn(ctx, move |_| simulate(&sim, &settings)));
+            }
+            if let Some(job) = &mut self.simulation_job {
+                if let Some(res) = job.poll() {
+                    self.simulation_job = None;
+                    match res.and_then(|res| res.map_err(|er| er.to_string())) {
+                        Ok(result) => {
+                            self.sim_result = result;
+                            self.sim_error = None;
+                        },
+                        Err(er) => self.sim_error = Some(er),
                     }
                 }
-
-
+            }
+            if let Some(er) = &self.sim_error {
+                ui.colored_label(ui.visuals().warn_fg_color, format!("Simulation failed: {}", er));
             }
 
         });
     }
 
     fn center_panel(&mut self, ui: &mut egui::Ui, ctx: &egui::Context) {
+        if self.local.has_curves() {
+            egui::SidePanel::right("local sensitivity").resizable(true).default_width(ui.available_width() / 3.).show_inside(ui, |ui| {
+                self.local.plot(ui);
+            });
+        }
+        if self.sweep.has_plot() {
+            egui::SidePanel::right("parameter sweep").resizable(true).default_width(ui.available_width() / 3.).show_inside(ui, |ui| {
+                self.sweep.plot(ui);
+            });
+        }
         let my_plot = Plot::new("main_plot")
             .legend(Legend::default().position(egui_plot::Corner::LeftTop))
             .x_axis_formatter(|gm, _max_n, _rng| {
@@ -385,6 +690,42 @@ impl Front for BionApp {
             
             let mut plot_points = self.point_nodes.plot_points();
 
+            // Viability
+            let viability_points = plot_points.pop();
+            if let Some(points) = viability_points {
+                plot_ui.points(points
+                    .radius(4.)
+                    .color(VIABILITY_COLOR)
+                );
+            }
+
+            // TCD
+            let tcd_points = plot_points.pop();
+            if let Some(points) = tcd_points {
+                plot_ui.points(points
+                    .radius(4.)
+                    .color(TCD_COLOR)
+                );
+            }
+
+            // Ammonia
+            let ammonia_points = plot_points.pop();
+            if let Some(points) = ammonia_points {
+                plot_ui.points(points
+                    .radius(4.)
+                    .color(AMMONIA_COLOR)
+                );
+            }
+
+            // Lactate
+            let lactate_points = plot_points.pop();
+            if let Some(points) = lactate_points {
+                plot_ui.points(points
+                    .radius(4.)
+                    .color(LACTATE_COLOR)
+                );
+            }
+
             // DO
             let do_points = plot_points.pop();
             if let Some(points) = do_points {
@@ -431,56 +772,36 @@ impl Front for BionApp {
             }
 
             // ------------------- show sim -------------------
-        
-            plot_ui.line(
-                Line::new(PlotPoints::from(self.sim_graphs.volume.clone()))
-                .name("Volume")
-                
-                .color(Color32::BLUE)
-            );
-            plot_ui.line(
-                Line::new(PlotPoints::from(self.sim_graphs.vcd.clone()))
-                .name("VCD")
-                
-                .color(Color32::RED)
-            );
-            plot_ui.line(
-                Line::new(PlotPoints::from(self.sim_graphs.glucose.clone()))
-                .name("Glucose")
-                
-                .color(Color32::GREEN)
-            );
-            
-            plot_ui.line(
-                Line::new(PlotPoints::from(self.sim_graphs.glutamin.clone()))
-                .name("Glutamin")
-                
-                .color(Color32::YELLOW)
-            );
-
-            let DO: Vec<[f64; 2]> = self.sim_graphs.c_O2.clone().into_iter().map(|[x,y]| {
-                [x, (y / self.sim.oxigen_saturation()) * 100.]
-            }).collect();
-
-            plot_ui.line(
-                Line::new(PlotPoints::from(DO))
-                .name("c_O2")
-                .color(Color32::WHITE)
-            );
-
-            plot_ui.line(
-                Line::new(PlotPoints::from(self.sim_graphs.O2.clone()))
-                .name("O2 input")
-                
-                .color(Color32::LIGHT_BLUE)
-            );
-            
-            plot_ui.line(
-                Line::new(PlotPoints::from(self.sim_graphs.product.clone()))
-                .name("Product")
-                
-                .color(Color32::GOLD)
-            );
+            let res = &self.sim_result;
+            for trace in res.traces() {
+                plot_ui.line(
+                    Line::new(PlotPoints::from(res.series(trace.values)))
+                    .name(trace.name)
+                    .style(trace.style)
+                    .color(trace.color)
+                );
+            }
+            if let Some(ensemble) = self.uncertainty.ensemble() {
+                let points = |values: &Vec<f64>| PlotPoints::from(ensemble.time.iter().zip(values).map(|(t, y)| [*t, *y]).collect::<Vec<_>>());
+                for band in &ensemble.bands {
+                    let color = band.color.gamma_multiply(0.6);
+                    plot_ui.line(Line::new(points(&band.median)).name(format!("{} median", band.name)).style(LineStyle::dotted_dense()).color(color));
+                    for values in [&band.lower, &band.upper] {
+                        plot_ui.line(Line::new(points(values)).name(format!("{} band", band.name)).style(LineStyle::dashed_loose()).color(color));
+                    }
+                }
+            }
+            for band in self.mcmc.bands(self.active_batch) {
+                let color = group_color(&band.group);
+                for values in [&band.lower, &band.upper] {
+                    plot_ui.line(
+                        Line::new(PlotPoints::from(band.time.iter().zip(values).map(|(t, y)| [*t, *y]).collect::<Vec<_>>()))
+                        .name(format!("{} 95% predictive", band.group))
+                        .style(LineStyle::dashed_loose())
+                        .color(color.gamma_multiply(0.6))
+                    );
+                }
+            }
             plot_ui.hline(
                 HLine::new(self.sim.airation.pid.minimum.clone())
                 .style(LineStyle::dashed_loose())
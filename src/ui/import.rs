@@ -0,0 +1,270 @@
+use std::{fs, path::PathBuf};
+
+use egui::{Grid, RichText, ScrollArea, TextEdit};
+
+use crate::{import::{read_workbook, units, ColumnMapping, Import, ImportProfile, Table, TimeFormat}, regressor::Group};
+
+const DELIMITERS: [(char, &str); 3] = [(',', "comma"), (';', "semicolon"), ('\t', "tab")];
+const WORKBOOKS: [&str; 5] = ["xlsx", "xlsm", "xlsb", "xls", "ods"];
+const PREVIEW_ROWS: usize = 10;
+
+#[derive(Debug)]
+enum Source {
+    Csv(String),
+    /// name and cells of every sheet
+    Workbook(Vec<(String, Vec<Vec<String>>)>),
+}
+
+/// the table of `sheet` as `profile` reads it
+fn read(source: &Source, sheet: usize, profile: &ImportProfile) -> Result<Table, String> {
+    match source {
+        Source::Csv(content) => Table::from_csv(content, profile.delimiter as u8),
+        Source::Workbook(sheets) => Table::from_grid(&sheets[sheet].1, &profile.range),
+    }
+}
+
+/// Preview of a measurement file where its columns are mapped to groups before anything is loaded
+#[derive(Debug)]
+pub struct ImportDialog {
+    pub name: String,
+    source: Source,
+    /// sheets to load, each into its own batch
+    selected: Vec<bool>,
+    /// sheet shown in the preview
+    sheet: usize,
+    table: Result<Table, String>,
+    pub profile: ImportProfile,
+    error: Option<String>,
+}
+
+impl ImportDialog {
+    /// reads `path` as a workbook or a CSV file by its extension and starts from `profile`
+    pub fn open(path: PathBuf, profile: &ImportProfile) -> Result<Self, String> {
+        let mut profile = profile.clone();
+        let extension = path.extension().map(|ext| ext.to_string_lossy().to_lowercase()).unwrap_or_default();
+        let source = if WORKBOOKS.contains(&extension.as_str()) {
+            let sheets = read_workbook(&path)?;
+            if sheets.is_empty() {
+                return Err("the workbook has no sheets".to_string());
+            }
+            Source::Workbook(sheets)
+        } else {
+            let content = fs::read_to_string(&path).map_err(|er| er.to_string())?;
+            if !content.lines().next().unwrap_or_default().contains(profile.delimiter) {
+                profile.delimiter = Table::sniff_delimiter(&content) as char;
+            }
+            Source::Csv(content)
+        };
+        let table = read(&source, 0, &profile);
+        let selected = match &source {
+            Source::Csv(_) => vec![true],
+            Source::Workbook(sheets) => vec![true; sheets.len()],
+        };
+        let name = path.file_name().map(|name| name.to_string_lossy().to_string()).unwrap_or_default();
+        Ok(Self { name, source, selected, sheet: 0, table, profile, error: None })
+    }
+
+    /// the measurements of every selected sheet with the batch name they go to once the user confirms,
+    /// `None` while the dialog stays open
+    pub fn show(&mut self, ctx: &egui::Context, open: &mut bool) -> Option<Vec<(String, Import)>> {
+        let mut out = None;
+        let mut keep = true;
+        egui::Window::new(format!("Import {}", self.name)).open(&mut keep).default_width(700.).show(ctx, |ui| {
+            // split up front, the closures below would otherwise each borrow all of self
+            let ImportDialog { name, source, selected, sheet, table: parsed, profile, error } = &mut *self;
+            let mut reread = false;
+            ui.horizontal(|ui| {
+                match source {
+                    Source::Csv(_) => {
+                        let delimiter = profile.delimiter;
+                        egui::ComboBox::from_label("delimiter")
+                            .selected_text(DELIMITERS.iter().find(|(d, _)| *d == delimiter).map(|(_, label)| *label).unwrap_or("other"))
+                            .show_ui(ui, |ui| {
+                                for (d, label) in DELIMITERS {
+                                    ui.selectable_value(&mut profile.delimiter, d, label);
+                                }
+                            });
+                        reread = profile.delimiter != delimiter;
+                    },
+                    Source::Workbook(_) => {
+                        ui.label("range");
+                        reread = ui.add(TextEdit::singleline(&mut profile.range).hint_text("whole sheet").desired_width(100.))
+                            .on_hover_text("cells holding the table with its header row, e.g. A1:F200 or B3")
+                            .changed();
+                    },
+                }
+                ui.separator();
+                if ui.button("Load profile").clicked() {
+                    if let Some(path) = rfd::FileDialog::new().add_filter("profile", &["json"]).pick_file() {
+                        match fs::read_to_string(path).map_err(|er| er.to_string()).and_then(|content| serde_json::from_str::<ImportProfile>(&content).map_err(|er| er.to_string())) {
+                            Ok(loaded) => {
+                                *profile = loaded;
+                                reread = true;
+                            },
+                            Err(er) => {
+                                println!("Error reading profile: {}", er);
+                                *error = Some(format!("Could not read the profile: {}", er));
+                            },
+                        }
+                    }
+                }
+                if ui.button("Save profile").clicked() {
+                    if let Some(mut path) = rfd::FileDialog::new().add_filter("profile", &["json"]).save_file() {
+                        path.set_extension("json");
+                        match serde_json::to_string_pretty(profile).map_err(|er| er.to_string()).and_then(|json| fs::write(path, json).map_err(|er| er.to_string())) {
+                            Ok(()) => *error = None,
+                            Err(er) => {
+                                println!("Error writing profile: {}", er);
+                                *error = Some(format!("Could not save the profile: {}", er));
+                            },
+                        }
+                    }
+                }
+            });
+
+            if let Source::Workbook(sheets) = source {
+                ui.horizontal_wrapped(|ui| {
+                    ui.label("sheets");
+                    for (i, (sheet_name, _)) in sheets.iter().enumerate() {
+                        ui.checkbox(&mut selected[i], "").on_hover_text("load as a batch");
+                        if ui.selectable_label(*sheet == i, sheet_name).clicked() && *sheet != i {
+                            *sheet = i;
+                            reread = true;
+                        }
+                    }
+                });
+            }
+            if reread {
+                *parsed = read(source, *sheet, profile);
+                *error = None;
+            }
+
+            let table = match parsed {
+                Ok(table) => table,
+                Err(er) => {
+                    ui.colored_label(ui.visuals().warn_fg_color, format!("Could not read the file: {}", er));
+                    return;
+                },
+            };
+
+            ui.horizontal(|ui| {
+                ui.label("time in");
+                for format in TimeFormat::ALL {
+                    ui.selectable_value(&mut profile.time_format, format, format.label());
+                }
+                if profile.time_format == TimeFormat::Timestamp {
+                    ui.add(TextEdit::singleline(&mut profile.origin).hint_text("first timestamp").desired_width(160.))
+                        .on_hover_text("minute zero as an ISO timestamp, e.g. 2024-03-01T08:00");
+                }
+            });
+
+            ScrollArea::horizontal().show(ui, |ui| {
+                Grid::new("import preview").striped(true).show(ui, |ui| {
+                    for header in &table.headers {
+                        ui.label(RichText::new(header).strong());
+                    }
+                    ui.end_row();
+                    for header in &table.headers {
+                        column_view(ui, profile, header);
+                    }
+                    ui.end_row();
+                    for row in table.rows.iter().take(PREVIEW_ROWS) {
+                        for i in 0..table.headers.len() {
+                            ui.label(row.get(i).map(|cell| cell.as_str()).unwrap_or_default());
+                        }
+                        ui.end_row();
+                    }
+                });
+            });
+            if table.rows.len() > PREVIEW_ROWS {
+                ui.label(format!("... {} more rows", table.rows.len() - PREVIEW_ROWS));
+            }
+            ui.separator();
+
+            match profile.apply(table) {
+                Ok(import) => {
+                    ui.label(format!("{} measurements", import.points.len()));
+                    if !import.missing.is_empty() {
+                        ui.label(format!("mapped but not in the file: {}", import.missing.join(", ")));
+                    }
+                    if !import.skipped.is_empty() {
+                        ui.colored_label(ui.visuals().warn_fg_color, format!("{} left out:", import.skipped.len()));
+                        ScrollArea::vertical().max_height(120.).show(ui, |ui| {
+                            for (row, reason) in &import.skipped {
+                                ui.label(format!("row {}: {}", row, reason));
+                            }
+                        });
+                    }
+                    if ui.add_enabled(!import.points.is_empty() && selected.contains(&true), egui::Button::new("Import")).clicked() {
+                        // every sheet goes through the same mapping as the previewed one, each using the mapped columns it has
+                        let imports: Vec<Result<(String, Import), String>> = match source {
+                            Source::Csv(_) => vec![Ok((name.clone(), import))],
+                            Source::Workbook(sheets) => sheets.iter().zip(selected.iter()).filter(|(_, load)| **load).map(|((sheet_name, grid), _)| {
+                                Table::from_grid(grid, &profile.range)
+                                    .and_then(|table| profile.apply(&table))
+                                    .map(|import| (sheet_name.clone(), import))
+                                    .map_err(|er| format!("{}: {}", sheet_name, er))
+                            }).collect(),
+                        };
+                        let failed: Vec<String> = imports.iter().filter_map(|res| res.as_ref().err().cloned()).collect();
+                        if failed.is_empty() {
+                            out = Some(imports.into_iter().flatten().collect());
+                        } else {
+                            *error = Some(format!("{}\nuntick these sheets to import the others", failed.join("\n")));
+                        }
+                    }
+                },
+                Err(er) => {
+                    ui.colored_label(ui.visuals().warn_fg_color, er);
+                },
+            }
+            if let Some(er) = error {
+                ui.colored_label(ui.visuals().warn_fg_color, er.as_str());
+            }
+        });
+        *open = keep && out.is_none();
+        out
+    }
+}
+
+/// what a column is read as, and its unit when it holds a group
+fn column_view(ui: &mut egui::Ui, profile: &mut ImportProfile, header: &str) {
+    let is_time = profile.time_column == header;
+    let mapped = profile.mapping(header).cloned();
+    let selected = match (&mapped, is_time) {
+        (_, true) => "time".to_string(),
+        (Some(mapping), _) => mapping.group.to_string(),
+        (None, false) => "-".to_string(),
+    };
+    ui.vertical(|ui| {
+        egui::ComboBox::from_id_source(("import column", header)).selected_text(selected).show_ui(ui, |ui| {
+            if ui.selectable_label(!is_time && mapped.is_none(), "-").clicked() {
+                profile.columns.retain(|mapping| mapping.column != header);
+                if is_time {
+                    profile.time_column.clear();
+                }
+            }
+            if ui.selectable_label(is_time, "time").clicked() {
+                profile.columns.retain(|mapping| mapping.column != header);
+                profile.time_column = header.to_string();
+            }
+            for group in Group::ALL {
+                let checked = mapped.as_ref().map(|mapping| mapping.group == group).unwrap_or(false);
+                if ui.selectable_label(checked, group.to_string()).clicked() && !checked {
+                    profile.columns.retain(|mapping| mapping.column != header);
+                    if is_time {
+                        profile.time_column.clear();
+                    }
+                    profile.columns.push(ColumnMapping { column: header.to_string(), unit: units(&group)[0].0.to_string(), group });
+                }
+            }
+        });
+        if let Some(mapping) = profile.columns.iter_mut().find(|mapping| mapping.column == header) {
+            egui::ComboBox::from_id_source(("import unit", header)).selected_text(mapping.unit.as_str()).show_ui(ui, |ui| {
+                for (unit, _) in units(&mapping.group) {
+                    ui.selectable_value(&mut mapping.unit, unit.to_string(), *unit);
+                }
+            });
+        }
+    });
+}
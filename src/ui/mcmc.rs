@@ -0,0 +1,158 @@
+use egui::{DragValue, Grid, Ui};
+use egui_plot::{Bar, BarChart, Line, Plot};
+
+use crate::{mcmc::{sample, Band, McmcSettings, Posterior, Prior}, regressor::{Group, Regressor}};
+
+use super::worker::{Progress, Worker};
+
+/// Posterior sampling of the checked minimization targets
+#[derive(Debug)]
+pub struct McmcTool {
+    settings: McmcSettings,
+    job: Option<Worker<Result<Posterior, String>>>,
+    result: Option<Result<Posterior, String>>,
+    show_bands: bool,
+}
+
+impl McmcTool {
+    pub fn default() -> Self {
+        Self {
+            settings: McmcSettings::default(),
+            job: None,
+            result: None,
+            show_bands: true,
+        }
+    }
+
+    /// predictive bands of `batch` to draw on the main plot
+    pub fn bands(&self, batch: usize) -> Vec<&Band> {
+        match &self.result {
+            Some(Ok(posterior)) if self.show_bands => posterior.bands.iter().filter(|band| band.batch == batch).collect(),
+            _ => Vec::new(),
+        }
+    }
+
+    pub fn view(&mut self, ui: &mut Ui, ctx: &egui::Context, regressor: &Regressor) {
+        ui.horizontal(|ui| {
+            ui.add(DragValue::new(&mut self.settings.samples).clamp_range(10..=1_000_000).prefix("samples: "));
+            ui.add(DragValue::new(&mut self.settings.burn_in).clamp_range(0..=1_000_000).prefix("burn-in: "));
+            ui.add(DragValue::new(&mut self.settings.draws).clamp_range(0..=1000).prefix("predictive draws: "));
+        });
+
+        ui.collapsing("Priors", |ui| {
+            Grid::new("mcmc priors").num_columns(3).show(ui, |ui| {
+                for fit in &regressor.param.targets {
+                    let value = fit.start(&regressor.batches[0].simulation);
+                    let mut prior = self.settings.prior(fit.target);
+                    ui.label(fit.target.label());
+                    egui::ComboBox::from_id_source(("prior", fit.target.label()))
+                        .selected_text(prior.label())
+                        .show_ui(ui, |ui| {
+                            let sd = (0.1 * value.abs()).max(1e-12);
+                            for option in [Prior::Uniform, Prior::Normal { mean: value, sd }, Prior::LogNormal { median: value.max(1e-12), sigma: 0.5 }] {
+                                let selected = std::mem::discriminant(&prior) == std::mem::discriminant(&option);
+                                if ui.selectable_label(selected, option.label()).clicked() && !selected {
+                                    prior = option;
+                                }
+                            }
+                        });
+                    ui.horizontal(|ui| {
+                        match &mut prior {
+                            Prior::Uniform => { ui.label(format!("[{:.3e}, {:.3e}]", fit.lower, fit.upper)); },
+                            Prior::Normal { mean, sd } => {
+                                let speed = (*sd).abs().max(1e-12) * 1e-2;
+                                ui.add(DragValue::new(mean).speed(speed).max_decimals(10).prefix("mean "));
+                                ui.add(DragValue::new(sd).speed(speed).max_decimals(10).clamp_range(1e-15..=f64::INFINITY).prefix("sd "));
+                            },
+                            Prior::LogNormal { median, sigma } => {
+                                let speed = (*median).abs().max(1e-12) * 1e-2;
+                                ui.add(DragValue::new(median).speed(speed).max_decimals(10).clamp_range(1e-15..=f64::INFINITY).prefix("median "));
+                                ui.add(DragValue::new(sigma).speed(0.01).clamp_range(1e-3..=10.).prefix("log sd "));
+                            },
+                        }
+                    });
+                    self.settings.set_prior(fit.target, prior);
+                    ui.end_row();
+                }
+            });
+        });
+
+        ui.collapsing("Measurement noise", |ui| {
+            Grid::new("mcmc noise").num_columns(3).show(ui, |ui| {
+                ui.label("");
+                ui.label("absolute sd");
+                ui.label("relative sd");
+                ui.end_row();
+                for group in Group::ALL {
+                    let noise = &mut self.settings.noise[group.index()];
+                    ui.label(group.to_string());
+                    ui.add(DragValue::new(&mut noise.absolute).speed(0.001).max_decimals(6).clamp_range(0.0..=f64::INFINITY));
+                    ui.add(DragValue::new(&mut noise.relative).speed(0.001).max_decimals(4).clamp_range(0.0..=10.));
+                    ui.end_row();
+                }
+            });
+        });
+
+        let running = self.job.is_some();
+        if ui.add_enabled(!running && !regressor.param.targets.is_empty(), egui::Button::new("Sample posterior")).clicked() {
+            let regressor = regressor.clone();
+            let settings = self.settings.clone();
+            self.result = None;
+            self.job = Some(Worker::spawn(ctx, move |reporter| {
+                let cancel = reporter.cancel_flag();
+                sample(regressor, settings, cancel, move |iter, log_posterior| reporter.progress(iter, log_posterior))
+            }));
+        }
+
+        if let Some(job) = &mut self.job {
+            let total = self.settings.burn_in + self.settings.samples;
+            let finished = job.show(ui, |Progress { iter, value }| format!("sample {} / {}, log posterior {:.4}", iter, total, value));
+            if let Some(res) = finished {
+                self.job = None;
+                self.result = Some(res.and_then(|res| res));
+            }
+        }
+
+        match &self.result {
+            Some(Ok(posterior)) => {
+                ui.label(format!("acceptance rate {:.1}%", 100. * posterior.acceptance));
+                ui.checkbox(&mut self.show_bands, "show 95% predictive bands");
+                Grid::new("mcmc summary").striped(true).num_columns(5).show(ui, |ui| {
+                    ui.strong("parameter");
+                    ui.strong("mean");
+                    ui.strong("sd");
+                    ui.strong("median");
+                    ui.strong("95% credible interval");
+                    ui.end_row();
+                    for (i, label) in posterior.labels.iter().enumerate() {
+                        let summary = posterior.summary(i);
+                        ui.label(label);
+                        ui.monospace(format!("{:.4e}", summary.mean));
+                        ui.monospace(format!("{:.3e}", summary.sd));
+                        ui.monospace(format!("{:.4e}", summary.quantiles[1]));
+                        ui.monospace(format!("[{:.4e}, {:.4e}]", summary.quantiles[0], summary.quantiles[2]));
+                        ui.end_row();
+                    }
+                });
+                for (i, label) in posterior.labels.iter().enumerate() {
+                    ui.label(label);
+                    ui.columns(2, |columns| {
+                        let trace: Vec<[f64; 2]> = posterior.column(i).into_iter().enumerate().map(|(k, v)| [k as f64, v]).collect();
+                        Plot::new(("mcmc trace", i)).height(120.).show(&mut columns[0], |plot_ui| {
+                            plot_ui.line(Line::new(trace).name("trace"));
+                        });
+                        let (bins, width) = posterior.histogram(i, 30);
+                        let bars = bins.into_iter().map(|[x, count]| Bar::new(x, count).width(width)).collect();
+                        Plot::new(("mcmc histogram", i)).height(120.).show(&mut columns[1], |plot_ui| {
+                            plot_ui.bar_chart(BarChart::new(bars).name("marginal"));
+                        });
+                    });
+                }
+            },
+            Some(Err(er)) => {
+                ui.colored_label(ui.visuals().warn_fg_color, format!("Sampling failed: {}", er));
+            },
+            None => {},
+        }
+    }
+}
@@ -3,6 +3,14 @@ use egui::Ui;
 
 pub mod tree;
 pub mod app;
+pub mod worker;
+pub mod report;
+pub mod profile;
+pub mod mcmc;
+pub mod sensitivity;
+pub mod uncertainty;
+pub mod sweep;
+pub mod import;
 
 pub trait Front {
     fn left_panel(&mut self, ui: &mut Ui, ctx: &egui::Context);
@@ -0,0 +1,97 @@
+use egui::{DragValue, Ui};
+use egui_plot::{HLine, Legend, Line, Plot, Points, VLine};
+
+use crate::{parameter::PARAMETERS, profile::{profile, Profile, ProfileSettings}, regressor::Regressor};
+
+use super::worker::{Progress, Worker};
+
+/// Profile likelihood of one target, the other minimization targets are re-fitted at every step
+#[derive(Debug)]
+pub struct ProfileTool {
+    settings: Option<ProfileSettings>,
+    job: Option<Worker<Result<Profile, String>>>,
+    result: Option<Result<Profile, String>>,
+}
+
+impl ProfileTool {
+    pub fn default() -> Self {
+        Self {
+            settings: None,
+            job: None,
+            result: None,
+        }
+    }
+
+    pub fn view(&mut self, ui: &mut Ui, ctx: &egui::Context, regressor: &Regressor) {
+        let sim = &regressor.batches[0].simulation;
+        let selected = self.settings.as_ref().map(|settings| settings.target.label()).unwrap_or("choose");
+        egui::ComboBox::from_label("profiled parameter")
+            .selected_text(selected)
+            .show_ui(ui, |ui| {
+                for target in PARAMETERS.iter() {
+                    let current = self.settings.as_ref().map(|settings| settings.target == target).unwrap_or(false);
+                    if ui.selectable_label(current, target.label()).on_hover_text(target.path()).clicked() && !current {
+                        self.settings = Some(ProfileSettings::new(target, target.get(sim)));
+                    }
+                }
+            });
+
+        let settings = match &mut self.settings {
+            Some(settings) => settings,
+            None => return,
+        };
+        let speed = (settings.upper - settings.lower).abs().max(1e-12) * 1e-2;
+        ui.horizontal(|ui| {
+            ui.add(DragValue::new(&mut settings.lower).speed(speed).max_decimals(10).prefix("from "));
+            ui.add(DragValue::new(&mut settings.upper).speed(speed).max_decimals(10).prefix("to "));
+        });
+        ui.horizontal(|ui| {
+            ui.add(DragValue::new(&mut settings.steps).clamp_range(2..=200).prefix("steps: "));
+            ui.checkbox(&mut settings.logarithmic, "logarithmic");
+        });
+        ui.label("The other checked minimization targets are re-fitted at every step.");
+
+        let running = self.job.is_some();
+        if ui.add_enabled(!running && settings.lower < settings.upper, egui::Button::new("Run profile")).clicked() {
+            let regressor = regressor.clone();
+            let settings = settings.clone();
+            self.result = None;
+            self.job = Some(Worker::spawn(ctx, move |reporter| {
+                let cancel = reporter.cancel_flag();
+                profile(regressor, settings, cancel, move |step, ssr| reporter.progress(step, ssr))
+            }));
+        }
+
+        if let Some(job) = &mut self.job {
+            let finished = job.show(ui, |Progress { iter, value }| format!("step {}, SSR {:.6e}", iter + 1, value));
+            if let Some(res) = finished {
+                self.job = None;
+                self.result = Some(res.and_then(|res| res));
+            }
+        }
+
+        match &self.result {
+            Some(Ok(profile)) => {
+                match profile.interval() {
+                    Some((low, high)) if profile.identifiable() => ui.label(format!("95% interval [{:.4e}, {:.4e}]", low, high)),
+                    Some(_) => ui.colored_label(ui.visuals().warn_fg_color, "Profile stays under the threshold at the end of the range, not identifiable there"),
+                    None => ui.label("Profile is empty"),
+                };
+                let current = profile.target.get(sim);
+                Plot::new("profile_plot")
+                    .height(200.)
+                    .legend(Legend::default())
+                    .show(ui, |plot_ui| {
+                        plot_ui.line(Line::new(profile.points.clone()).name("SSR"));
+                        plot_ui.points(Points::new(profile.points.clone()).radius(3.));
+                        plot_ui.hline(HLine::new(profile.threshold).name("95% threshold"));
+                        plot_ui.vline(VLine::new(current).name("current"));
+                    });
+            },
+            Some(Err(er)) => {
+                ui.colored_label(ui.visuals().warn_fg_color, format!("Profile failed: {}", er));
+            },
+            None => {},
+        }
+    }
+}
@@ -0,0 +1,99 @@
+use egui::{Grid, RichText, Ui};
+
+use crate::regressor::Fit;
+
+/// Parameters with their uncertainty, correlations and residuals of one fit
+pub fn fit_report(ui: &mut Ui, fit: &Fit) {
+    ui.label(format!("{}: best cost {:.6e} ({}) after {} iterations", fit.optimizer, fit.best_cost, fit.metric.label(), fit.iterations));
+    ui.label(&fit.termination);
+
+    let best = match &fit.best_param {
+        Some(best) => best,
+        None => {
+            ui.label("No parameters were found.");
+            return;
+        }
+    };
+    let confidence = fit.confidence.as_ref().ok();
+
+    ui.separator();
+    Grid::new("fit parameters").striped(true).num_columns(4).show(ui, |ui| {
+        ui.strong("parameter");
+        ui.strong("value");
+        ui.strong("std error");
+        ui.strong("95% interval");
+        ui.end_row();
+        for (i, (label, value)) in fit.labels.iter().zip(best).enumerate() {
+            ui.label(label);
+            ui.monospace(format!("{:.4e}", value));
+            match confidence {
+                Some(confidence) => {
+                    let se = confidence.std_errors[i];
+                    ui.monospace(format!("{:.3e} ({:.1}%)", se, 100. * se / value.abs()));
+                    ui.monospace(format!("[{:.4e}, {:.4e}]", confidence.lower[i], confidence.upper[i]));
+                },
+                None => {
+                    ui.label("-");
+                    ui.label("-");
+                },
+            }
+            ui.end_row();
+        }
+    });
+
+    match &fit.confidence {
+        Ok(confidence) => {
+            ui.label(format!("residual std {:.4e} with {} degrees of freedom", confidence.sigma, confidence.dof));
+            if fit.targets.len() > 1 {
+                ui.label("Correlation");
+                Grid::new("fit correlation").num_columns(fit.targets.len() + 1).show(ui, |ui| {
+                    ui.label("");
+                    for label in &fit.labels {
+                        ui.strong(label);
+                    }
+                    ui.end_row();
+                    for (label, row) in fit.labels.iter().zip(&confidence.correlation) {
+                        ui.strong(label);
+                        for r in row {
+                            // strongly correlated pairs cannot be told apart by the data
+                            let text = if r.is_finite() { RichText::new(format!("{:+.3}", r)) } else { RichText::new("-") }.monospace();
+                            if r.abs() > 0.95 {
+                                ui.label(text.color(ui.visuals().warn_fg_color));
+                            } else {
+                                ui.label(text);
+                            }
+                        }
+                        ui.end_row();
+                    }
+                });
+            }
+        },
+        Err(er) => {
+            ui.colored_label(ui.visuals().warn_fg_color, format!("No confidence intervals: {}", er));
+        },
+    }
+
+    ui.separator();
+    Grid::new("fit residuals").striped(true).num_columns(6).show(ui, |ui| {
+        ui.strong("group");
+        ui.strong("n");
+        ui.strong("SSE");
+        ui.strong("weighted SSE");
+        ui.strong("NRMSE");
+        ui.strong("cost");
+        ui.end_row();
+        for res in &fit.residuals {
+            if fit.batch_names.len() > 1 {
+                ui.label(format!("{}: {}", fit.batch_names[res.batch], res.group));
+            } else {
+                ui.label(res.group.to_string());
+            }
+            ui.label(res.n.to_string());
+            ui.monospace(format!("{:.4e}", res.sse));
+            ui.monospace(format!("{:.4e}", res.weighted_sse));
+            ui.monospace(format!("{:.4}", res.nrmse));
+            ui.monospace(format!("{:.4e}", res.cost(&fit.metric)));
+            ui.end_row();
+        }
+    });
+}
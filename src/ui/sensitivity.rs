@@ -0,0 +1,269 @@
+use egui::{DragValue, Grid, Ui};
+use egui_plot::{Bar, BarChart, HLine, Legend, Line, Plot, PlotPoints};
+
+use crate::{model::Bioreactor, parameter::{self, Parameter, PARAMETERS}, regressor::Group, sensitivity::{analyse, local, Factor, LocalSensitivity, Method, Output, Sensitivity, SensitivitySettings}, simulation::SimSettings};
+
+use super::worker::{Progress, Worker};
+
+/// Morris screening and Sobol indices of the chosen bioreactor parameters
+#[derive(Debug)]
+pub struct SensitivityTool {
+    settings: SensitivitySettings,
+    job: Option<Worker<Result<Sensitivity, String>>>,
+    result: Option<Result<Sensitivity, String>>,
+    output: Output,
+}
+
+impl SensitivityTool {
+    pub fn default() -> Self {
+        Self {
+            settings: SensitivitySettings::default(),
+            job: None,
+            result: None,
+            output: Output::FinalTiter,
+        }
+    }
+
+    pub fn view(&mut self, ui: &mut Ui, ctx: &egui::Context, sim: &Bioreactor, sim_settings: &SimSettings) {
+        let mut add = Vec::new();
+        ui.horizontal(|ui| {
+            egui::ComboBox::from_id_source("add factor").selected_text("add factor").show_ui(ui, |ui| {
+                for param in PARAMETERS.iter().filter(|param| !self.settings.factors.iter().any(|factor| factor.parameter == *param)) {
+                    if ui.selectable_label(false, format!("{} [{}]", param.path(), param.unit())).clicked() {
+                        add.push(param);
+                    }
+                }
+            });
+            if ui.button("all").clicked() {
+                add.extend(PARAMETERS.iter().filter(|param| !self.settings.factors.iter().any(|factor| factor.parameter == *param)));
+            }
+            if ui.button("none").clicked() {
+                self.settings.factors.clear();
+            }
+        });
+        self.settings.factors.extend(add.into_iter().map(|param| Factor::new(param, sim)));
+
+        let mut remove = None;
+        Grid::new("sensitivity factors").num_columns(4).show(ui, |ui| {
+            for (i, factor) in self.settings.factors.iter_mut().enumerate() {
+                let speed = (factor.upper - factor.lower).abs().max(1e-12) * 1e-2;
+                ui.label(factor.parameter.label()).on_hover_text(factor.parameter.path());
+                ui.add(DragValue::new(&mut factor.lower).speed(speed).max_decimals(10).clamp_range(f64::NEG_INFINITY..=factor.upper).prefix("from "));
+                ui.add(DragValue::new(&mut factor.upper).speed(speed).max_decimals(10).clamp_range(factor.lower..=f64::INFINITY).prefix("to "));
+                if ui.small_button("x").clicked() {
+                    remove = Some(i);
+                }
+                ui.end_row();
+            }
+        });
+        if let Some(i) = remove {
+            self.settings.factors.remove(i);
+        }
+
+        ui.horizontal(|ui| {
+            let morris = matches!(self.settings.method, Method::Morris { .. });
+            if ui.selectable_label(morris, "Morris").clicked() && !morris {
+                self.settings.method = Method::Morris { trajectories: 10, levels: 4 };
+            }
+            if ui.selectable_label(!morris, "Sobol").clicked() && morris {
+                self.settings.method = Method::Sobol { samples: 64 };
+            }
+            match &mut self.settings.method {
+                Method::Morris { trajectories, levels } => {
+                    ui.add(DragValue::new(trajectories).clamp_range(2..=1000).prefix("trajectories: "));
+                    ui.add(DragValue::new(levels).clamp_range(2..=20).prefix("levels: "));
+                },
+                Method::Sobol { samples } => {
+                    ui.add(DragValue::new(samples).clamp_range(8..=10_000).prefix("samples: "));
+                },
+            }
+        });
+        let total = self.settings.method.runs(self.settings.factors.len());
+        ui.label(format!("{} simulations", total));
+
+        let running = self.job.is_some();
+        if ui.add_enabled(!running && !self.settings.factors.is_empty(), egui::Button::new("Run analysis")).clicked() {
+            let sim = sim.clone();
+            let sim_settings = sim_settings.clone();
+            let settings = self.settings.clone();
+            self.result = None;
+            self.job = Some(Worker::spawn(ctx, move |reporter| {
+                let cancel = reporter.cancel_flag();
+                analyse(sim, sim_settings, settings, cancel, move |run| reporter.progress(run, 0.))
+            }));
+        }
+
+        if let Some(job) = &mut self.job {
+            let finished = job.show(ui, |Progress { iter, .. }| format!("simulation {} / {}", iter, total));
+            if let Some(res) = finished {
+                self.job = None;
+                self.result = Some(res.and_then(|res| res));
+            }
+        }
+
+        let mut selected = self.output;
+        match &self.result {
+            Some(Ok(sensitivity)) => {
+                if sensitivity.failed > 0 {
+                    ui.colored_label(ui.visuals().warn_fg_color, format!("{} of {} simulations failed and were left out", sensitivity.failed, sensitivity.runs));
+                }
+                ui.horizontal(|ui| {
+                    for output in Output::ALL {
+                        ui.selectable_value(&mut selected, output, output.label());
+                    }
+                });
+                let indices = match sensitivity.indices.iter().find(|(output, _)| *output == selected) {
+                    Some((_, indices)) => indices,
+                    None => &[][..],
+                };
+                let undefined: Vec<&str> = indices.iter().zip(&sensitivity.factors).filter(|(index, _)| index.is_none()).map(|(_, param)| param.label()).collect();
+                if !undefined.is_empty() {
+                    ui.label(format!("n/a for {}: the output did not vary or every run failed", undefined.join(", ")));
+                }
+                let names = sensitivity.method.indices();
+                let labels: Vec<String> = sensitivity.factors.iter().map(|param| param.label().to_string()).collect();
+                let charts: Vec<BarChart> = (0..2).map(|j| {
+                    let offset = if j == 0 { -0.2 } else { 0.2 };
+                    let bars = indices.iter().enumerate()
+                        .filter_map(|(i, index)| index.map(|index| Bar::new(i as f64 + offset, index[j]).width(0.4).name(&labels[i])))
+                        .collect();
+                    BarChart::new(bars).name(names[j])
+                }).collect();
+                Plot::new("sensitivity_plot")
+                    .height(220.)
+                    .legend(Legend::default())
+                    .x_axis_formatter(move |mark, _, _| {
+                        let i = mark.value.round();
+                        if (mark.value - i).abs() < 1e-6 && i >= 0. {
+                            labels.get(i as usize).cloned().unwrap_or_default()
+                        } else {
+                            String::new()
+                        }
+                    })
+                    .show(ui, |plot_ui| {
+                        for chart in charts {
+                            plot_ui.bar_chart(chart);
+                        }
+                    });
+            },
+            Some(Err(er)) => {
+                ui.colored_label(ui.visuals().warn_fg_color, format!("Analysis failed: {}", er));
+            },
+            None => {},
+        }
+        self.output = selected;
+    }
+}
+
+/// one entry per parameter, so a failed one does not hide the rest
+type LocalResult = Result<Vec<Result<LocalSensitivity, String>>, String>;
+
+/// ∂state/∂parameter over the time course, drawn beside the main plot
+#[derive(Debug)]
+pub struct LocalTool {
+    parameters: Vec<&'static Parameter>,
+    group: Group,
+    scaled: bool,
+    pub show: bool,
+    job: Option<Worker<LocalResult>>,
+    result: Option<LocalResult>,
+}
+
+impl LocalTool {
+    pub fn default() -> Self {
+        Self {
+            parameters: vec![parameter::by_path("mu_max"), parameter::by_path("constants.kDO")],
+            group: Group::VCD,
+            scaled: true,
+            show: true,
+            job: None,
+            result: None,
+        }
+    }
+
+    pub fn view(&mut self, ui: &mut Ui, ctx: &egui::Context, sim: &Bioreactor, sim_settings: &SimSettings) {
+        let mut toggled = None;
+        ui.horizontal_wrapped(|ui| {
+            for param in &self.parameters {
+                let mut selected = true;
+                if ui.checkbox(&mut selected, param.label()).on_hover_text(param.path()).changed() {
+                    toggled = Some(*param);
+                }
+            }
+            egui::ComboBox::from_id_source("add local parameter").selected_text("add parameter").show_ui(ui, |ui| {
+                for param in PARAMETERS.iter().filter(|param| !self.parameters.contains(param)) {
+                    if ui.selectable_label(false, format!("{} [{}]", param.path(), param.unit())).clicked() {
+                        toggled = Some(param);
+                    }
+                }
+            });
+        });
+        if let Some(param) = toggled {
+            match self.parameters.iter().position(|p| *p == param) {
+                Some(i) => { self.parameters.remove(i); },
+                None => self.parameters.push(param),
+            }
+        }
+        ui.horizontal_wrapped(|ui| {
+            for group in Group::ALL {
+                let label = group.to_string();
+                ui.selectable_value(&mut self.group, group, label);
+            }
+        });
+        ui.horizontal(|ui| {
+            ui.checkbox(&mut self.scaled, "scaled by the parameter value");
+            ui.checkbox(&mut self.show, "show");
+        });
+
+        let total = 2 * self.parameters.len() + 1;
+        if ui.add_enabled(self.job.is_none() && !self.parameters.is_empty(), egui::Button::new("Compute")).clicked() {
+            let sim = sim.clone();
+            let sim_settings = sim_settings.clone();
+            let parameters = self.parameters.clone();
+            self.job = Some(Worker::spawn(ctx, move |reporter| {
+                let cancel = reporter.cancel_flag();
+                local(sim, sim_settings, parameters, cancel, move |run| reporter.progress(run, 0.))
+            }));
+        }
+        if let Some(job) = &mut self.job {
+            let finished = job.show(ui, |Progress { iter, .. }| format!("simulation {} / {}", iter, total));
+            if let Some(res) = finished {
+                self.job = None;
+                self.result = Some(res.and_then(|res| res));
+            }
+        }
+        match &self.result {
+            Some(Ok(sensitivities)) => {
+                for er in sensitivities.iter().filter_map(|res| res.as_ref().err()) {
+                    ui.colored_label(ui.visuals().warn_fg_color, format!("Sensitivity failed for {}", er));
+                }
+            },
+            Some(Err(er)) => {
+                ui.colored_label(ui.visuals().warn_fg_color, format!("Sensitivity failed: {}", er));
+            },
+            None => {},
+        }
+    }
+
+    pub fn has_curves(&self) -> bool {
+        self.show && matches!(&self.result, Some(Ok(sensitivities)) if sensitivities.iter().any(|res| res.is_ok()))
+    }
+
+    pub fn plot(&self, ui: &mut Ui) {
+        let sensitivities = match &self.result {
+            Some(Ok(sensitivities)) => sensitivities,
+            _ => return,
+        };
+        let unit = if self.scaled { "" } else { " per unit" };
+        ui.label(format!("∂{}/∂p{}", self.group, unit));
+        Plot::new("local_sensitivity_plot")
+            .legend(Legend::default().position(egui_plot::Corner::LeftTop))
+            .x_axis_formatter(|mark, _, _| format!("Day {:.2}", mark.value / (60. * 24.)))
+            .show(ui, |plot_ui| {
+                for sensitivity in sensitivities.iter().flatten() {
+                    plot_ui.line(Line::new(PlotPoints::from(sensitivity.series(&self.group, self.scaled))).name(sensitivity.parameter.label()));
+                }
+                plot_ui.hline(HLine::new(0.).color(egui::Color32::DARK_GRAY));
+            });
+    }
+}
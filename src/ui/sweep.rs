@@ -0,0 +1,192 @@
+use egui::{Color32, DragValue, Ui};
+use egui_plot::{Legend, Line, Plot, PlotPoints, Polygon};
+
+use crate::{model::Bioreactor, parameter::{Parameter, PARAMETERS}, sensitivity::Output, simulation::SimSettings, sweep::{sweep, Axis, Sweep, SweepSettings}};
+
+use super::worker::{Progress, Worker};
+
+/// blue at 0 to red at 1
+fn ramp(t: f64) -> Color32 {
+    let t = if t.is_finite() { t.clamp(0., 1.) } else { 0.5 };
+    Color32::from_rgb((40. + 215. * t) as u8, 60, (255. - 215. * t) as u8)
+}
+
+/// Runs the model over a grid of one or two parameters
+#[derive(Debug)]
+pub struct SweepTool {
+    settings: SweepSettings,
+    job: Option<Worker<Result<Sweep, String>>>,
+    result: Option<Result<Sweep, String>>,
+    /// trace overlaid for a 1D sweep, by its legend name
+    trace: &'static str,
+    /// KPI coloured in a 2D sweep
+    output: Output,
+    pub show: bool,
+}
+
+impl SweepTool {
+    pub fn default() -> Self {
+        Self {
+            settings: SweepSettings::default(),
+            job: None,
+            result: None,
+            trace: "Product",
+            output: Output::FinalTiter,
+            show: true,
+        }
+    }
+
+    /// `other` is the parameter on the other axis, which this one cannot take
+    fn axis_view(ui: &mut Ui, id: &str, axis: &mut Axis, other: Option<&Parameter>, sim: &Bioreactor) {
+        ui.horizontal(|ui| {
+            let mut picked = None;
+            egui::ComboBox::from_id_source(id).selected_text(axis.parameter.label()).show_ui(ui, |ui| {
+                for param in PARAMETERS.iter().filter(|param| Some(*param) != other) {
+                    if ui.selectable_label(param == axis.parameter, format!("{} [{}]", param.path(), param.unit())).clicked() {
+                        picked = Some(param);
+                    }
+                }
+            }).response.on_hover_text(axis.parameter.path());
+            if let Some(param) = picked {
+                if param != axis.parameter {
+                    *axis = Axis { steps: axis.steps, ..Axis::new(param, sim) };
+                }
+            }
+            let speed = (axis.to - axis.from).abs().max(1e-12) * 1e-2;
+            ui.add(DragValue::new(&mut axis.from).speed(speed).max_decimals(10).prefix("from "));
+            ui.add(DragValue::new(&mut axis.to).speed(speed).max_decimals(10).prefix("to "));
+            ui.add(DragValue::new(&mut axis.steps).clamp_range(1..=200).prefix("steps: "));
+        });
+    }
+
+    pub fn view(&mut self, ui: &mut Ui, ctx: &egui::Context, sim: &Bioreactor, sim_settings: &SimSettings) {
+        let y = self.settings.y.as_ref().map(|axis| axis.parameter);
+        Self::axis_view(ui, "sweep x", &mut self.settings.x, y, sim);
+        let mut second = self.settings.y.is_some();
+        if ui.checkbox(&mut second, "second parameter (heatmap)").changed() {
+            self.settings.y = if second { Some(Axis::new(PARAMETERS.iter().find(|param| *param != self.settings.x.parameter).unwrap(), sim)) } else { None };
+        }
+        if let Some(axis) = &mut self.settings.y {
+            Self::axis_view(ui, "sweep y", axis, Some(self.settings.x.parameter), sim);
+        }
+
+        match &self.result {
+            Some(Ok(Sweep::Curves { runs, .. })) => {
+                let names: Vec<&'static str> = runs.first().map(|(_, res)| res.traces().iter().map(|trace| trace.name).collect()).unwrap_or_default();
+                egui::ComboBox::from_id_source("sweep trace").selected_text(self.trace).show_ui(ui, |ui| {
+                    for name in names {
+                        ui.selectable_value(&mut self.trace, name, name);
+                    }
+                });
+            },
+            Some(Ok(Sweep::Heatmap { .. })) => {
+                ui.horizontal(|ui| {
+                    for output in Output::ALL {
+                        ui.selectable_value(&mut self.output, output, output.label());
+                    }
+                });
+            },
+            _ => {},
+        }
+
+        let total = self.settings.runs();
+        ui.horizontal(|ui| {
+            ui.label(format!("{} simulations", total));
+            ui.checkbox(&mut self.show, "show");
+        });
+        let running = self.job.is_some();
+        if ui.add_enabled(!running && total > 0, egui::Button::new("Run sweep")).clicked() {
+            let sim = sim.clone();
+            let sim_settings = sim_settings.clone();
+            let settings = self.settings.clone();
+            self.result = None;
+            self.job = Some(Worker::spawn(ctx, move |reporter| {
+                let cancel = reporter.cancel_flag();
+                sweep(sim, sim_settings, settings, cancel, move |run| reporter.progress(run, 0.))
+            }));
+        }
+
+        if let Some(job) = &mut self.job {
+            let finished = job.show(ui, |Progress { iter, .. }| format!("simulation {} / {}", iter, total));
+            if let Some(res) = finished {
+                self.job = None;
+                self.result = Some(res.and_then(|res| res));
+            }
+        }
+
+        match &self.result {
+            Some(Ok(sweep)) if sweep.failed() > 0 => {
+                ui.colored_label(ui.visuals().warn_fg_color, format!("{} simulations failed", sweep.failed()));
+            },
+            Some(Err(er)) => {
+                ui.colored_label(ui.visuals().warn_fg_color, format!("Sweep failed: {}", er));
+            },
+            _ => {},
+        }
+    }
+
+    pub fn has_plot(&self) -> bool {
+        self.show && matches!(self.result, Some(Ok(_)))
+    }
+
+    pub fn plot(&self, ui: &mut Ui) {
+        match &self.result {
+            Some(Ok(Sweep::Curves { parameter, runs, .. })) => {
+                ui.label(format!("{} over {}", self.trace, parameter.label()));
+                let n = runs.len().max(2) - 1;
+                Plot::new("sweep_plot")
+                    .legend(Legend::default().position(egui_plot::Corner::LeftTop))
+                    .x_axis_formatter(|mark, _, _| format!("Day {:.2}", mark.value / (60. * 24.)))
+                    .show(ui, |plot_ui| {
+                        for (i, (value, res)) in runs.iter().enumerate() {
+                            if let Some(trace) = res.traces().iter().find(|trace| trace.name == self.trace) {
+                                plot_ui.line(
+                                    Line::new(PlotPoints::from(res.series(trace.values)))
+                                    .name(format!("{} = {:.4}", parameter.label(), value))
+                                    .color(ramp(i as f64 / n as f64))
+                                );
+                            }
+                        }
+                    });
+            },
+            Some(Ok(Sweep::Heatmap { x, y, xs, ys, values, .. })) => {
+                let values = &values[Output::ALL.iter().position(|output| *output == self.output).unwrap_or(0)];
+                let finite = values.iter().copied().filter(|v| v.is_finite());
+                let (min, max) = finite.fold((f64::INFINITY, f64::NEG_INFINITY), |(min, max), v| (min.min(v), max.max(v)));
+                ui.horizontal(|ui| {
+                    ui.label(format!("{}:", self.output.label()));
+                    ui.colored_label(ramp(0.), format!("{:.4}", min));
+                    ui.label("to");
+                    ui.colored_label(ramp(1.), format!("{:.4}", max));
+                });
+                // cells are centred on the grid points and reach halfway to their neighbours
+                let half = |values: &[f64]| if values.len() > 1 { (values[1] - values[0]) / 2. } else { 0.5 };
+                let (dx, dy) = (half(xs), half(ys));
+                let (x_label, y_label, kpi) = (x.label().to_string(), y.label().to_string(), self.output.label());
+                Plot::new("sweep_heatmap")
+                    .x_axis_label(format!("{} [{}]", x.label(), x.unit()))
+                    .y_axis_label(format!("{} [{}]", y.label(), y.unit()))
+                    .label_formatter(move |name, point| {
+                        let at = format!("{} = {:.4}\n{} = {:.4}", x_label, point.x, y_label, point.y);
+                        if name.is_empty() { at } else { format!("{} = {}\n{}", kpi, name, at) }
+                    })
+                    .show(ui, |plot_ui| {
+                        for (j, yv) in ys.iter().enumerate() {
+                            for (i, xv) in xs.iter().enumerate() {
+                                let value = values[j * xs.len() + i];
+                                let color = if value.is_finite() { ramp((value - min) / (max - min)) } else { Color32::DARK_GRAY };
+                                let corners = vec![[xv - dx, yv - dy], [xv + dx, yv - dy], [xv + dx, yv + dy], [xv - dx, yv + dy]];
+                                plot_ui.polygon(
+                                    Polygon::new(PlotPoints::from(corners))
+                                    .name(format!("{:.4}", value))
+                                    .fill_color(color)
+                                    .stroke(egui::Stroke::new(0.5, color))
+                                );
+                            }
+                        }
+                    });
+            },
+            _ => {},
+        }
+    }
+}
@@ -0,0 +1,132 @@
+use egui::{DragValue, Grid, Ui};
+
+use crate::{model::Bioreactor, parameter::PARAMETERS, simulation::SimSettings, uncertainty::{propagate, Distribution, Ensemble, Uncertain, UncertaintySettings}};
+
+use super::worker::{Progress, Worker};
+
+/// Spread of every trace when parameters are only known as distributions
+#[derive(Debug)]
+pub struct UncertaintyTool {
+    settings: UncertaintySettings,
+    job: Option<Worker<Result<Ensemble, String>>>,
+    result: Option<Result<Ensemble, String>>,
+    show: bool,
+}
+
+impl UncertaintyTool {
+    pub fn default() -> Self {
+        Self {
+            settings: UncertaintySettings::default(),
+            job: None,
+            result: None,
+            show: true,
+        }
+    }
+
+    /// the ensemble to draw on the main plot
+    pub fn ensemble(&self) -> Option<&Ensemble> {
+        match &self.result {
+            Some(Ok(ensemble)) if self.show => Some(ensemble),
+            _ => None,
+        }
+    }
+
+    pub fn view(&mut self, ui: &mut Ui, ctx: &egui::Context, sim: &Bioreactor, sim_settings: &SimSettings) {
+        let mut add = None;
+        egui::ComboBox::from_id_source("add uncertain").selected_text("add parameter").show_ui(ui, |ui| {
+            for param in PARAMETERS.iter().filter(|param| !self.settings.parameters.iter().any(|uncertain| uncertain.parameter == *param)) {
+                if ui.selectable_label(false, format!("{} [{}]", param.path(), param.unit())).clicked() {
+                    add = Some(param);
+                }
+            }
+        });
+        if let Some(param) = add {
+            let [normal, _, _] = Distribution::around(param.get(sim));
+            self.settings.parameters.push(Uncertain { parameter: param, distribution: normal });
+        }
+
+        let mut remove = None;
+        Grid::new("uncertain parameters").num_columns(4).show(ui, |ui| {
+            for (i, uncertain) in self.settings.parameters.iter_mut().enumerate() {
+                let value = uncertain.parameter.get(sim);
+                ui.label(uncertain.parameter.label()).on_hover_text(uncertain.parameter.path());
+                egui::ComboBox::from_id_source(("distribution", uncertain.parameter.path()))
+                    .selected_text(uncertain.distribution.label())
+                    .show_ui(ui, |ui| {
+                        for option in Distribution::around(value) {
+                            let selected = std::mem::discriminant(&uncertain.distribution) == std::mem::discriminant(&option);
+                            if ui.selectable_label(selected, option.label()).clicked() && !selected {
+                                uncertain.distribution = option;
+                            }
+                        }
+                    });
+                ui.horizontal(|ui| {
+                    let speed = value.abs().max(1e-12) * 1e-2;
+                    match &mut uncertain.distribution {
+                        Distribution::Normal { mean, sd } => {
+                            ui.add(DragValue::new(mean).speed(speed).max_decimals(10).prefix("mean "));
+                            ui.add(DragValue::new(sd).speed(speed).max_decimals(10).clamp_range(0.0..=f64::INFINITY).prefix("sd "));
+                        },
+                        Distribution::LogNormal { median, sigma } => {
+                            ui.add(DragValue::new(median).speed(speed).max_decimals(10).clamp_range(1e-15..=f64::INFINITY).prefix("median "));
+                            ui.add(DragValue::new(sigma).speed(0.01).clamp_range(0.0..=10.).prefix("log sd "));
+                        },
+                        Distribution::Uniform { lower, upper } => {
+                            ui.add(DragValue::new(lower).speed(speed).max_decimals(10).clamp_range(f64::NEG_INFINITY..=*upper).prefix("from "));
+                            ui.add(DragValue::new(upper).speed(speed).max_decimals(10).clamp_range(*lower..=f64::INFINITY).prefix("to "));
+                        },
+                    }
+                });
+                if ui.small_button("x").clicked() {
+                    remove = Some(i);
+                }
+                ui.end_row();
+            }
+        });
+        if let Some(i) = remove {
+            self.settings.parameters.remove(i);
+        }
+
+        ui.horizontal(|ui| {
+            ui.add(DragValue::new(&mut self.settings.runs).clamp_range(2..=100_000).prefix("runs: "));
+            ui.add(DragValue::new(&mut self.settings.coverage).clamp_range(1.0..=99.9).suffix("% band"));
+            ui.checkbox(&mut self.show, "show");
+        });
+
+        let running = self.job.is_some();
+        if ui.add_enabled(!running && !self.settings.parameters.is_empty(), egui::Button::new("Run ensemble")).clicked() {
+            let sim = sim.clone();
+            let sim_settings = sim_settings.clone();
+            let settings = self.settings.clone();
+            self.result = None;
+            self.job = Some(Worker::spawn(ctx, move |reporter| {
+                let cancel = reporter.cancel_flag();
+                propagate(sim, sim_settings, settings, cancel, move |run| reporter.progress(run, 0.))
+            }));
+        }
+
+        let runs = self.settings.runs;
+        if let Some(job) = &mut self.job {
+            let finished = job.show(ui, |Progress { iter, .. }| format!("simulation {} / {}", iter, runs));
+            if let Some(res) = finished {
+                self.job = None;
+                self.result = Some(res.and_then(|res| res));
+            }
+        }
+
+        match &self.result {
+            Some(Ok(ensemble)) => {
+                if ensemble.rejected > 0 {
+                    ui.label(format!("{} draws below zero were drawn again", ensemble.rejected));
+                }
+                if ensemble.failed > 0 {
+                    ui.colored_label(ui.visuals().warn_fg_color, format!("{} simulations failed and were left out", ensemble.failed));
+                }
+            },
+            Some(Err(er)) => {
+                ui.colored_label(ui.visuals().warn_fg_color, format!("Ensemble failed: {}", er));
+            },
+            _ => {},
+        }
+    }
+}
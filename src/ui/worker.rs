@@ -0,0 +1,114 @@
+use std::{sync::{atomic::{AtomicBool, Ordering}, mpsc::{self, Receiver, Sender, TryRecvError}, Arc}, thread};
+
+/// `value` is whatever the job tracks next to its iteration, e.g. the best cost of a fit
+#[derive(Debug, Clone, Copy)]
+pub struct Progress {
+    pub iter: u64,
+    pub value: f64,
+}
+
+/// Handed to the job so it can report progress and see if it was cancelled
+#[derive(Clone)]
+pub struct Reporter {
+    sender: Sender<Progress>,
+    cancel: Arc<AtomicBool>,
+    ctx: egui::Context,
+}
+impl Reporter {
+    pub fn progress(&self, iter: u64, value: f64) {
+        let _ = self.sender.send(Progress { iter, value });
+        self.ctx.request_repaint();
+    }
+
+    pub fn cancel_flag(&self) -> Arc<AtomicBool> {
+        self.cancel.clone()
+    }
+}
+
+/// A job running on its own thread, polled from the UI every frame.
+/// Dropping the worker asks the job to cancel.
+pub struct Worker<T> {
+    result: Receiver<T>,
+    progress: Receiver<Progress>,
+    cancel: Arc<AtomicBool>,
+    last_progress: Option<Progress>,
+}
+
+impl<T: Send + 'static> Worker<T> {
+    pub fn spawn<F>(ctx: &egui::Context, job: F) -> Self
+    where
+        F: FnOnce(Reporter) -> T + Send + 'static,
+    {
+        let (result_sender, result) = mpsc::channel();
+        let (sender, progress) = mpsc::channel();
+        let cancel = Arc::new(AtomicBool::new(false));
+        let reporter = Reporter { sender, cancel: cancel.clone(), ctx: ctx.clone() };
+        let ctx = ctx.clone();
+
+        thread::spawn(move || {
+            let out = job(reporter);
+            let _ = result_sender.send(out);
+            ctx.request_repaint();
+        });
+
+        Self {
+            result,
+            progress,
+            cancel,
+            last_progress: None,
+        }
+    }
+}
+
+impl<T> Worker<T> {
+    pub fn cancel(&self) {
+        self.cancel.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancel.load(Ordering::Relaxed)
+    }
+
+    /// Collects the progress sent so far and returns the result once the job is done.
+    pub fn poll(&mut self) -> Option<Result<T, String>> {
+        while let Ok(progress) = self.progress.try_recv() {
+            self.last_progress = Some(progress);
+        }
+        match self.result.try_recv() {
+            Ok(out) => Some(Ok(out)),
+            Err(TryRecvError::Empty) => None,
+            Err(TryRecvError::Disconnected) => Some(Err("worker thread stopped unexpectedly".to_string())),
+        }
+    }
+
+    /// Polls the job under a spinner with its progress described by `label` and a Cancel button.
+    pub fn show(&mut self, ui: &mut egui::Ui, label: impl FnOnce(Progress) -> String) -> Option<Result<T, String>> {
+        let finished = self.poll();
+        ui.horizontal(|ui| {
+            ui.spinner();
+            match self.last_progress {
+                Some(progress) => ui.label(label(progress)),
+                None => ui.label("Calculating..."),
+            };
+            if ui.add_enabled(!self.is_cancelled(), egui::Button::new("Cancel")).clicked() {
+                self.cancel();
+            }
+        });
+        finished
+    }
+}
+
+impl<T> std::fmt::Debug for Worker<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Worker")
+            .field("last_progress", &self.last_progress)
+            .field("cancelled", &self.is_cancelled())
+            .finish()
+    }
+}
+
+impl<T> Drop for Worker<T> {
+    fn drop(&mut self) {
+        self.cancel();
+    }
+}
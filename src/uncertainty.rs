@@ -0,0 +1,215 @@
+use std::sync::{atomic::{AtomicBool, Ordering}, Arc};
+
+use egui::Color32;
+use rand::Rng;
+use rand_distr::StandardNormal;
+
+use crate::{ensemble::simulate_each, mcmc::quantile, model::Bioreactor, parameter::Parameter, simulation::{SimSettings, SimulationResult}};
+
+/// Spread of an uncertain parameter
+#[derive(Debug, Clone, PartialEq)]
+pub enum Distribution {
+    Normal { mean: f64, sd: f64 },
+    LogNormal { median: f64, sigma: f64 },
+    Uniform { lower: f64, upper: f64 },
+}
+impl Distribution {
+    pub fn label(&self) -> &'static str {
+        match self {
+            Distribution::Normal { .. } => "Normal",
+            Distribution::LogNormal { .. } => "Log-normal",
+            Distribution::Uniform { .. } => "Uniform",
+        }
+    }
+
+    /// the three kinds centred on `value`
+    pub fn around(value: f64) -> [Distribution; 3] {
+        let spread = (0.1 * value.abs()).max(1e-12);
+        [
+            Distribution::Normal { mean: value, sd: spread },
+            Distribution::LogNormal { median: value.abs().max(1e-12), sigma: 0.1 },
+            Distribution::Uniform { lower: value - spread, upper: value + spread },
+        ]
+    }
+
+    pub fn sample(&self, rng: &mut impl Rng) -> f64 {
+        match *self {
+            Distribution::Normal { mean, sd } => mean + sd * rng.sample::<f64, _>(StandardNormal),
+            Distribution::LogNormal { median, sigma } => median * (sigma * rng.sample::<f64, _>(StandardNormal)).exp(),
+            Distribution::Uniform { lower, upper } => lower + (upper - lower) * rng.gen::<f64>(),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Uncertain {
+    pub parameter: &'static Parameter,
+    pub distribution: Distribution,
+}
+
+#[derive(Debug, Clone)]
+pub struct UncertaintySettings {
+    pub parameters: Vec<Uncertain>,
+    pub runs: usize,
+    /// share of the runs inside the band [%]
+    pub coverage: f64,
+}
+impl UncertaintySettings {
+    pub fn default() -> Self {
+        Self {
+            parameters: Vec::new(),
+            runs: 200,
+            coverage: 90.,
+        }
+    }
+}
+
+/// Median and percentile band of one simulated trace
+#[derive(Debug, Clone)]
+pub struct TraceBand {
+    pub name: &'static str,
+    pub color: Color32,
+    pub median: Vec<f64>,
+    pub lower: Vec<f64>,
+    pub upper: Vec<f64>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Ensemble {
+    pub time: Vec<f64>,
+    pub bands: Vec<TraceBand>,
+    pub failed: usize,
+    /// draws thrown away and drawn again because a value fell outside its physical domain
+    pub rejected: usize,
+}
+
+/// redraws before a distribution is taken to lie outside the domain altogether
+const MAX_REDRAWS: usize = 1000;
+
+/// `runs` copies of `sim` with the uncertain parameters drawn, and how many draws were thrown away
+fn draw(sim: &Bioreactor, parameters: &[Uncertain], runs: usize, rng: &mut impl Rng) -> Result<(Vec<Bioreactor>, usize), String> {
+    let mut rejected = 0;
+    let mut sims = Vec::with_capacity(runs);
+    for _ in 0..runs {
+        let mut sim = sim.clone();
+        for uncertain in parameters {
+            // rejection keeps the shape of the distribution inside the domain, clamping would pile draws up on its edge
+            let mut tries = 0;
+            let val = loop {
+                let val = uncertain.distribution.sample(rng);
+                if uncertain.parameter.admits(val) {
+                    break val;
+                }
+                rejected += 1;
+                tries += 1;
+                if tries == MAX_REDRAWS {
+                    return Err(format!("the {} distribution of {} lies outside the values it can take", uncertain.distribution.label(), uncertain.parameter.label()));
+                }
+            };
+            uncertain.parameter.set(&mut sim, val);
+        }
+        sims.push(sim);
+    }
+    Ok((sims, rejected))
+}
+
+/// lower bound, median and upper bound of `values` with `tail` of them left out on either side
+fn band(mut values: Vec<f64>, tail: f64) -> [f64; 3] {
+    values.retain(|y| y.is_finite());
+    values.sort_by(|a, b| a.partial_cmp(b).unwrap());
+    [quantile(&values, tail), quantile(&values, 0.5), quantile(&values, 1. - tail)]
+}
+
+/// Simulates `settings.runs` draws of the uncertain parameters on all cores
+pub fn propagate<F>(sim: Bioreactor, sim_settings: SimSettings, settings: UncertaintySettings, cancel: Arc<AtomicBool>, mut progress: F) -> Result<Ensemble, String>
+where
+    F: FnMut(u64),
+{
+    let (sims, rejected) = draw(&sim, &settings.parameters, settings.runs, &mut rand::thread_rng())?;
+
+    let results: Vec<Option<SimulationResult>> = simulate_each(&sims, &sim_settings, &cancel, |run| progress(run as u64))
+        .into_iter()
+        .map(|res| res.and_then(|res| res.ok()))
+        .collect();
+    if cancel.load(Ordering::Relaxed) {
+        return Err("Cancelled".to_string());
+    }
+
+    let time = match results.iter().flatten().next() {
+        Some(first) => first.time.clone(),
+        None => return Err("every simulation failed".to_string()),
+    };
+    let done: Vec<&SimulationResult> = results.iter().flatten().filter(|res| res.time.len() == time.len()).collect();
+    let failed = settings.runs - done.len();
+
+    let tail = (100. - settings.coverage.clamp(0., 100.)) / 200.;
+    let traces: Vec<_> = done.iter().map(|res| res.traces()).collect();
+    let bands = traces[0].iter().enumerate().map(|(j, trace)| {
+        let mut band = TraceBand { name: trace.name, color: trace.color, median: Vec::with_capacity(time.len()), lower: Vec::with_capacity(time.len()), upper: Vec::with_capacity(time.len()) };
+        for k in 0..time.len() {
+            let [lower, median, upper] = self::band(traces.iter().map(|trace| trace[j].values[k]).collect(), tail);
+            band.median.push(median);
+            band.lower.push(lower);
+            band.upper.push(upper);
+        }
+        band
+    }).collect();
+
+    Ok(Ensemble { time, bands, failed, rejected })
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::{rngs::StdRng, SeedableRng};
+
+    use crate::parameter;
+
+    use super::*;
+
+    fn uncertain(path: &str, distribution: Distribution) -> Uncertain {
+        Uncertain { parameter: parameter::by_path(path), distribution }
+    }
+
+    #[test]
+    fn log_normal_is_spread_around_its_median() {
+        let distribution = Distribution::LogNormal { median: 2., sigma: 0.5 };
+        let mut rng = StdRng::seed_from_u64(3);
+        let mut values: Vec<f64> = (0..20_000).map(|_| distribution.sample(&mut rng)).collect();
+        assert!(values.iter().all(|v| *v > 0.));
+        values.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        assert!((quantile(&values, 0.5) - 2.).abs() < 0.03);
+        // sigma is the sd of the log
+        let logs: Vec<f64> = values.iter().map(|v| v.ln()).collect();
+        let mean = logs.iter().sum::<f64>() / logs.len() as f64;
+        let sd = (logs.iter().map(|l| (l - mean).powi(2)).sum::<f64>() / logs.len() as f64).sqrt();
+        assert!((sd - 0.5).abs() < 0.01, "{}", sd);
+    }
+
+    #[test]
+    fn draws_outside_the_domain_are_drawn_again() {
+        // half of this distribution lies below zero, where a rate makes no sense
+        let parameters = [uncertain("mu_max", Distribution::Normal { mean: 0., sd: 1e-4 })];
+        let mut rng = StdRng::seed_from_u64(5);
+        let (sims, rejected) = draw(&Bioreactor::default(), &parameters, 1000, &mut rng).unwrap();
+        assert_eq!(sims.len(), 1000);
+        assert!(sims.iter().all(|sim| sim.mu_max >= 0.));
+        // about one rejection per accepted draw
+        assert!(rejected > 850 && rejected < 1150, "{}", rejected);
+    }
+
+    #[test]
+    fn a_distribution_outside_the_domain_is_an_error() {
+        let parameters = [uncertain("mu_max", Distribution::Uniform { lower: -2., upper: -1. })];
+        let mut rng = StdRng::seed_from_u64(5);
+        assert!(draw(&Bioreactor::default(), &parameters, 10, &mut rng).is_err());
+    }
+
+    #[test]
+    fn band_leaves_out_the_tails() {
+        // 0 to 100 with failed runs mixed in
+        let mut values: Vec<f64> = (0..=100).rev().map(f64::from).collect();
+        values.extend([f64::NAN, f64::INFINITY]);
+        assert_eq!(band(values, 0.05), [5., 50., 95.]);
+        assert!(band(vec![f64::NAN], 0.05).iter().all(|y| y.is_nan()));
+    }
+}